@@ -0,0 +1,55 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use hey::{Bits, Node, UniversalEntropy};
+
+/// Tree sizes, in leaves, up to the 10^6 the fold has to stay fast for.
+const SIZES: [usize; 4] = [1_000, 10_000, 100_000, 1_000_000];
+
+fn tree(leaves: usize) -> Node {
+    Node::from(Bits::repeat(true, leaves))
+}
+
+fn fold(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fold");
+    group.sample_size(10);
+    for leaves in SIZES {
+        let node = tree(leaves);
+        let mut entropy = UniversalEntropy::new();
+        group.bench_with_input(BenchmarkId::from_parameter(leaves), &node, |b, node| {
+            b.iter(|| node.fold(&mut entropy, black_box(leaves / 2)))
+        });
+    }
+    group.finish();
+}
+
+fn op(c: &mut Criterion) {
+    let mut group = c.benchmark_group("op");
+    group.sample_size(10);
+    for leaves in SIZES {
+        let node = tree(leaves);
+        let input = Node::from(Bits::from_slice(b"hey,\n"));
+        let mut entropy = UniversalEntropy::new();
+        group.bench_with_input(BenchmarkId::from_parameter(leaves), &node, |b, node| {
+            b.iter(|| node.op(&mut entropy, black_box(&input)))
+        });
+    }
+    group.finish();
+}
+
+fn reflect(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reflect");
+    group.sample_size(10);
+    for leaves in SIZES {
+        let mut node = tree(leaves);
+        let input = Node::from(Bits::from_slice(b"hey,\n"));
+        let mut entropy = UniversalEntropy::new();
+        group.bench_function(BenchmarkId::from_parameter(leaves), |b| {
+            b.iter(|| node.reflect(&mut entropy, black_box(&input)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fold, op, reflect);
+criterion_main!(benches);
@@ -0,0 +1,102 @@
+//! Self-describing binary encoding of Node trees.
+//!
+//! Layout: `[version u8][tree bits…][zero padding to a byte boundary]`.
+//!
+//! The tree is written in pre-order with a prefix code:
+//! - `1`      → Compound, followed by its left then right subtree
+//! - `0 b`    → Bit(b)
+//!
+//! The code is self-delimiting, so a decoder knows where the tree ends
+//! without a length field; anything after the padding is rejected.
+
+use bitvec::prelude::*;
+use std::io;
+
+use crate::node::{Bits, Node};
+
+/// Current encoding version, written as the first byte of every encoding.
+pub const VERSION: u8 = 1;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Encode a Node tree into its versioned, prefix-coded byte form.
+pub fn encode(node: &Node) -> Vec<u8> {
+    let mut out = Bits::with_capacity(8 + 3 * node.size());
+    out.extend_from_bitslice(VERSION.view_bits::<Msb0>());
+
+    let mut stack = vec![node];
+    while let Some(next) = stack.pop() {
+        match next {
+            Node::Bit(b) => {
+                out.push(false);
+                out.push(*b);
+            }
+            Node::Compound(compound) => {
+                out.push(true);
+                let (left, right) = compound.children();
+                stack.push(right);
+                stack.push(left);
+            }
+        }
+    }
+
+    out.into_vec()
+}
+
+/// Decode bytes produced by [`encode`] back into the same Node tree.
+///
+/// Fails with `InvalidData` on an unknown version, a truncated tree,
+/// non-zero padding or trailing bytes.
+pub fn decode(bytes: &[u8]) -> io::Result<Node> {
+    let (&version, body) = bytes
+        .split_first()
+        .ok_or_else(|| invalid("empty node encoding"))?;
+    if version != VERSION {
+        return Err(invalid(format!(
+            "unsupported node encoding version {}",
+            version
+        )));
+    }
+
+    let bits = body.view_bits::<Msb0>();
+    let mut pos = 0;
+    let mut read = || -> io::Result<bool> {
+        let bit = *bits
+            .get(pos)
+            .ok_or_else(|| invalid("truncated node encoding"))?;
+        pos += 1;
+        Ok(bit)
+    };
+
+    // Open compounds: `None` still waits for its left child,
+    // `Some(left)` has it and waits for the right one.
+    let mut open: Vec<Option<Node>> = Vec::new();
+
+    let root = 'decode: loop {
+        if read()? {
+            open.push(None);
+            continue;
+        }
+
+        let mut node = Node::Bit(read()?);
+        loop {
+            match open.pop() {
+                None => break 'decode node,
+                Some(None) => {
+                    open.push(Some(node));
+                    break;
+                }
+                Some(Some(left)) => node = Node::compound(left, node),
+            }
+        }
+    };
+
+    let rest = &bits[pos..];
+    if rest.len() >= 8 || rest.any() {
+        return Err(invalid("trailing data after node encoding"));
+    }
+
+    Ok(root)
+}
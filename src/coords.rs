@@ -0,0 +1,172 @@
+//! Internal coordinates of atoms, from the README's `f`, `g` and `H`.
+//!
+//! - `f(x) = x^(e/x^e)`, largest at `x = e^(1/e)`, where `f(x) = e^(1/e)`
+//! - `g(x) = x^(e/x)`, largest at `x = e`, where `g(x) = e`
+//! - `H(u) = u·e^(1−u)`, a single peak `H(1) = 1`, falling to 0 at both ends
+//!
+//! An atom is a byte of a Node's leaves (see [`atoms`]), mapped to a
+//! positive code `x(c)` by [`code`]. Its internal coordinate is
+//! `d_int = H(f(x))`, its shadow coordinate `d_ext = H(g(x))` and its
+//! entropic weight `w = −ln H(g(x))`. A Node's coordinate is the
+//! `e^(−w)`-weighted barycenter of its atoms' `d_int` (see [`superpose`]).
+//!
+//! Everything is computed from logarithms — `ln f(x) = e·ln x·e^(−e·ln x)`
+//! and `ln H(u) = −(u − 1 − ln u)` — so nothing overflows as `x → ∞`,
+//! underflow at `x → 0⁺` lands on the true limit 0, and `H` and `w` keep
+//! full precision where `u` is close to 1.
+
+use std::f64::consts::E;
+
+use crate::node::{Bits, Node};
+
+/// `f(x) = x^(e/x^e)` for `x ≥ 0`, with the limits `f(0) = 0` and
+/// `f(∞) = 1`. NaN outside the domain.
+pub fn f(x: f64) -> f64 {
+    ln_f(x).exp()
+}
+
+/// `g(x) = x^(e/x)` for `x ≥ 0`, with the limits `g(0) = 0` and
+/// `g(∞) = 1`. NaN outside the domain.
+pub fn g(x: f64) -> f64 {
+    ln_g(x).exp()
+}
+
+/// `H(u) = u·e^(1−u)` for `u ≥ 0`, with `H(0) = H(∞) = 0`. NaN outside
+/// the domain.
+pub fn h(u: f64) -> f64 {
+    (-excess(ln(u))).exp()
+}
+
+/// The entropic weight `−ln H(u)`, accurate even where it is tiny
+/// (`u` near 1).
+pub fn weight(u: f64) -> f64 {
+    excess(ln(u))
+}
+
+/// The code `x(c) = e^(1/e) · 2^((c − 128) / 128)` of the atom `c`.
+///
+/// Codes spread over one octave either side of `e^(1/e)`, where `f` peaks:
+/// the atom 128 sits exactly on the peak.
+pub fn code(c: u8) -> f64 {
+    E.powf(1.0 / E) * ((c as f64 - 128.0) / 128.0).exp2()
+}
+
+/// The atoms of `node`: the bytes of its leaves, the last one zero padded.
+pub fn atoms(node: &Node) -> Vec<u8> {
+    Bits::from(node).into_vec()
+}
+
+/// Every coordinate of one atom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atom {
+    pub byte: u8,
+    /// `x(c)`.
+    pub x: f64,
+    /// `f(x)`: the internal view.
+    pub u_int: f64,
+    /// `g(x)`: the external view.
+    pub u_ext: f64,
+    /// `H(u_int)`: the canonical embedding.
+    pub d_int: f64,
+    /// `H(u_ext)`: the shadow coordinate.
+    pub d_ext: f64,
+    /// `−ln H(u_ext)`: the entropic weight.
+    pub weight: f64,
+}
+
+impl Atom {
+    /// The coordinates of the atom `byte`, at its code [`code`]`(byte)`.
+    pub fn new(byte: u8) -> Self {
+        let x = code(byte);
+        let (ln_int, ln_ext) = (ln_f(x), ln_g(x));
+        let weight = excess(ln_ext);
+        Atom {
+            byte,
+            x,
+            u_int: ln_int.exp(),
+            u_ext: ln_ext.exp(),
+            d_int: (-excess(ln_int)).exp(),
+            d_ext: (-weight).exp(),
+            weight,
+        }
+    }
+}
+
+/// The superposition `d = Σ d_int·e^(−w) / Σ e^(−w)` of `atoms`, or `None`
+/// for no atoms.
+///
+/// The sum runs over the multiset of atoms in byte order, so the same
+/// atoms in any order give bit-for-bit the same coordinate.
+pub fn superpose(atoms: impl IntoIterator<Item = u8>) -> Option<f64> {
+    let mut counts = [0u64; 256];
+    let mut any = false;
+    for atom in atoms {
+        counts[atom as usize] += 1;
+        any = true;
+    }
+    if !any {
+        return None;
+    }
+
+    let (mut weighted, mut total) = (0.0, 0.0);
+    for (byte, &count) in counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let atom = Atom::new(byte as u8);
+        weighted += count as f64 * atom.d_int * atom.d_ext;
+        total += count as f64 * atom.d_ext;
+    }
+    Some(weighted / total)
+}
+
+/// The internal coordinate of `node`: the superposition of its atoms.
+pub fn coordinate(node: &Node) -> f64 {
+    superpose(atoms(node)).expect("a node has at least one leaf")
+}
+
+/// `ln x`, NaN below 0.
+fn ln(x: f64) -> f64 {
+    if x < 0.0 {
+        f64::NAN
+    } else {
+        x.ln()
+    }
+}
+
+/// `ln f(x) = e·ln x·e^(−e·ln x)`.
+fn ln_f(x: f64) -> f64 {
+    if x == f64::INFINITY {
+        return 0.0;
+    }
+    let lx = ln(x);
+    E * lx * (-E * lx).exp()
+}
+
+/// `ln g(x) = e·ln x·e^(−ln x)`.
+fn ln_g(x: f64) -> f64 {
+    if x == f64::INFINITY {
+        return 0.0;
+    }
+    let lx = ln(x);
+    E * lx * (-lx).exp()
+}
+
+/// `e^l − 1 − l`, which is `u − 1 − ln u` for `l = ln u`: the series
+/// near 0, where the direct form cancels to nothing.
+fn excess(l: f64) -> f64 {
+    if l == f64::INFINITY {
+        return f64::INFINITY;
+    }
+    if l.abs() < 0.01 {
+        // l²/2! + l³/3! + … + l⁷/7!, by Horner.
+        let mut sum = 0.0;
+        let mut k = 7.0;
+        while k >= 2.0 {
+            sum = (sum + 1.0) * l / k;
+            k -= 1.0;
+        }
+        return sum * l;
+    }
+    l.exp_m1() - l
+}
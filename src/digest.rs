@@ -0,0 +1,206 @@
+//! Content digests of Node trees.
+//!
+//! A digest is a Merkle-style BLAKE3 hash over the tree:
+//!
+//! - `Bit(b)`            → `BLAKE3(0x00 || b)`
+//! - `Compound(l, r)`    → `BLAKE3(0x01 || digest(l) || digest(r))`
+//!
+//! It depends only on the tree's shape and leaves, so it is stable across
+//! runs, platforms and peers. The root digest is a node's compact identity;
+//! a [`MerkleTree`] keeps the digest of every subtree, so two trees can be
+//! compared top-down and only the subtrees that differ looked at.
+
+use bitvec::prelude::*;
+use std::fmt;
+
+use crate::node::{Bits, Node};
+
+const LEAF: u8 = 0x00;
+const COMPOUND: u8 = 0x01;
+
+/// A 32 byte content digest.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest([u8; 32]);
+
+impl Digest {
+    /// Digest of the leaf `b`.
+    pub fn leaf(b: bool) -> Digest {
+        Digest(*blake3::hash(&[LEAF, b as u8]).as_bytes())
+    }
+
+    /// Digest of a compound from the digests of its children.
+    pub fn compound(left: &Digest, right: &Digest) -> Digest {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[COMPOUND]);
+        hasher.update(&left.0);
+        hasher.update(&right.0);
+        Digest(*hasher.finalize().as_bytes())
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Digest {
+        Digest(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The first 8 bytes in hex: enough to tell nodes apart in logs.
+    pub fn short(&self) -> String {
+        self.to_string()[..16].to_string()
+    }
+}
+
+/// Lowercase hex of all 32 bytes.
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({})", self)
+    }
+}
+
+/// Digest of one subtree, stored in pre-order.
+#[derive(Debug, Clone)]
+struct Entry {
+    digest: Digest,
+    /// Index of the right child; the left child directly follows its
+    /// parent. `None` for a leaf.
+    right: Option<usize>,
+}
+
+/// The digest of every subtree of a Node, addressed by path from the root
+/// (`0` = left, `1` = right), like a [`NodePatch`](crate::NodePatch).
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    entries: Vec<Entry>,
+}
+
+impl MerkleTree {
+    /// Hash every subtree of `node` in one iterative pass.
+    pub fn new(node: &Node) -> Self {
+        enum Task<'a> {
+            /// Append the entry for a subtree; `Some(parent)` when it is
+            /// that parent's right child.
+            Visit(&'a Node, Option<usize>),
+            /// Both children of the compound at this entry are hashed.
+            Seal(usize),
+        }
+
+        let (zero, one) = (Digest::leaf(false), Digest::leaf(true));
+        let mut entries: Vec<Entry> = Vec::with_capacity(2 * node.size() - 1);
+        let mut todo = vec![Task::Visit(node, None)];
+        while let Some(task) = todo.pop() {
+            match task {
+                Task::Visit(node, right_of) => {
+                    let at = entries.len();
+                    if let Some(parent) = right_of {
+                        entries[parent].right = Some(at);
+                    }
+                    match node {
+                        Node::Bit(b) => entries.push(Entry {
+                            digest: if *b { one } else { zero },
+                            right: None,
+                        }),
+                        Node::Compound(compound) => {
+                            let (left, right) = compound.children();
+                            entries.push(Entry {
+                                digest: zero,
+                                right: None,
+                            });
+                            todo.push(Task::Seal(at));
+                            todo.push(Task::Visit(right, Some(at)));
+                            todo.push(Task::Visit(left, None));
+                        }
+                    }
+                }
+                Task::Seal(at) => {
+                    let right = entries[at].right.expect("right child visited");
+                    entries[at].digest =
+                        Digest::compound(&entries[at + 1].digest, &entries[right].digest);
+                }
+            }
+        }
+
+        MerkleTree { entries }
+    }
+
+    /// The root digest: the tree's identity, equal to [`Node::fingerprint`].
+    pub fn root(&self) -> Digest {
+        self.entries[0].digest
+    }
+
+    fn find(&self, path: &BitSlice<u8, Msb0>) -> Option<usize> {
+        let mut at = 0;
+        for side in path.iter().by_vals() {
+            let right = self.entries[at].right?;
+            at = if side { right } else { at + 1 };
+        }
+        Some(at)
+    }
+
+    /// Digest of the subtree at `path`, if the tree reaches that far.
+    pub fn get(&self, path: &BitSlice<u8, Msb0>) -> Option<Digest> {
+        self.find(path).map(|at| self.entries[at].digest)
+    }
+
+    /// Digests of the two children of the compound at `path`; `None` for a
+    /// leaf or a path outside the tree.
+    pub fn children(&self, path: &BitSlice<u8, Msb0>) -> Option<(Digest, Digest)> {
+        let at = self.find(path)?;
+        let right = self.entries[at].right?;
+        Some((self.entries[at + 1].digest, self.entries[right].digest))
+    }
+
+    /// Every subtree's path and digest, in pre-order.
+    pub fn iter(&self) -> impl Iterator<Item = (Bits, Digest)> + '_ {
+        let mut todo = vec![(0, Bits::new())];
+        std::iter::from_fn(move || {
+            let (at, path) = todo.pop()?;
+            let entry = &self.entries[at];
+            if let Some(right) = entry.right {
+                let (mut left_path, mut right_path) = (path.clone(), path.clone());
+                left_path.push(false);
+                right_path.push(true);
+                todo.push((right, right_path));
+                todo.push((at + 1, left_path));
+            }
+            Some((path, entry.digest))
+        })
+    }
+
+    /// Paths of the largest subtrees whose digests differ from `other`'s.
+    ///
+    /// Matching subtrees are skipped without descending; where both sides
+    /// are compounds the search goes on into the children, otherwise the
+    /// whole subtree is reported.
+    pub fn mismatches(&self, other: &MerkleTree) -> Vec<Bits> {
+        let mut out = Vec::new();
+        let mut todo = vec![(0, 0, Bits::new())];
+        while let Some((mine, theirs, path)) = todo.pop() {
+            let (a, b) = (&self.entries[mine], &other.entries[theirs]);
+            if a.digest == b.digest {
+                continue;
+            }
+            match (a.right, b.right) {
+                (Some(a_right), Some(b_right)) => {
+                    let mut right = path.clone();
+                    right.push(true);
+                    todo.push((a_right, b_right, right));
+                    let mut left = path;
+                    left.push(false);
+                    todo.push((mine + 1, theirs + 1, left));
+                }
+                _ => out.push(path),
+            }
+        }
+        out
+    }
+}
@@ -1,92 +1,553 @@
 use bitvec::prelude::*;
+use num_bigint::BigUint;
+use std::ops::Range;
+
+use crate::digest::Digest;
 
 /// Bit-level buffer type (same as your `Bits` alias).
 pub type Bits = BitVec<u8, Msb0>;
 
-/// Universal entropy source, conceptually "bits of e^(1/e)".
+/// A stream of bits the Node algebra folds against: `e(pos)` in
+/// [`Node::fold`](crate::Node::fold), [`Node::op`](crate::Node::op) and
+/// [`Node::reflect`](crate::Node::reflect).
+///
+/// A source must answer the same position with the same bit until it is
+/// [`reset`](EntropySource::reset).
+pub trait EntropySource {
+    /// The bit at `pos`.
+    fn bit(&mut self, pos: usize) -> bool;
+
+    /// The bits in `[start, start + len)`.
+    fn range(&mut self, start: usize, len: usize) -> Bits {
+        (start..start + len).map(|pos| self.bit(pos)).collect()
+    }
+
+    /// Return to the state the source was created in.
+    fn reset(&mut self);
+
+    /// Identity of the stream: the digest of its first
+    /// [`FINGERPRINT_BITS`] bits. Peers comparing fingerprints can tell
+    /// whether they fold against the same stream.
+    fn fingerprint(&mut self) -> Digest {
+        let bits = self.range(0, FINGERPRINT_BITS);
+        Digest::from_bytes(*blake3::hash(bits.as_raw_slice()).as_bytes())
+    }
+}
+
+/// Bits hashed into an [`EntropySource::fingerprint`].
+pub const FINGERPRINT_BITS: usize = 256;
+
+/// Universal entropy source: the binary expansion of e^(1/e).
+///
+/// Bit `pos` of the stream is fractional bit `pos + 1` of
+/// e^(1/e) = 1.0111000111010101…₂, so the stream starts `0111000111…`.
 ///
-/// Internally this keeps:
-/// - a mutable generator state `f`
-/// - the current step index `n`
-/// - a growing buffer of generated bits
+/// The expansion is computed exactly with integer arithmetic (see
+/// [`expansion`]), so every platform produces the same bits. Any position
+/// can be reached directly, by computing the expansion to that precision,
+/// without generating the positions before it.
 ///
-/// Each step advances `f` using your iterative rule:
-///     f_{n+1} = 1 + f_n / (n+1)
-/// and extracts one bit from the floating-point representation.
-/// The bits are deterministic and can be reproduced anywhere
-/// by replaying the generator.
+/// Only a window of at most `capacity` consecutive bits is held. Reading
+/// past the window computes a new one reaching twice as far (up to the
+/// capacity), so a forward scan recomputes only a few times, and the bits
+/// falling out of it are dropped. Reading before the window computes a
+/// window there. Computing a window ending at `p` briefly needs about
+/// `p / 8` bytes of working memory, whatever the capacity.
 #[derive(Debug, Clone)]
 pub struct UniversalEntropy {
-    n: u64,
-    f: f64,
-    bits: Bits,
+    /// Bits held, starting at stream position `start`.
+    window: Bits,
+    start: usize,
+    capacity: usize,
+    /// Next position read by the sequential API ([`fill_bytes`],
+    /// [`next_u32`], [`next_u64`], [`iter`]).
+    ///
+    /// [`fill_bytes`]: UniversalEntropy::fill_bytes
+    /// [`next_u32`]: UniversalEntropy::next_u32
+    /// [`next_u64`]: UniversalEntropy::next_u64
+    /// [`iter`]: UniversalEntropy::iter
+    cursor: usize,
+}
+
+/// Smallest precision computed at once, in bits.
+const MIN_PRECISION: usize = 1024;
+
+/// Most bits held by default: 1 MiB of stream.
+pub const DEFAULT_CAPACITY: usize = 8 << 20;
+
+impl Default for UniversalEntropy {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl UniversalEntropy {
-    /// Create a new universal entropy generator.
-    ///
-    /// `f` is initialised near e^(1/e) to make the intent explicit,
-    /// but the important part is that the generator is deterministic
-    /// and shared, not that it numerically equals e^(1/e).
+    /// Create a new universal entropy generator holding at most
+    /// [`DEFAULT_CAPACITY`] bits.
     pub fn new() -> Self {
-        let f0 = std::f64::consts::E.powf(1.0 / std::f64::consts::E); // ≈ 1.444667...
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Create a generator holding at most `capacity` bits (at least one).
+    pub fn with_capacity(capacity: usize) -> Self {
         UniversalEntropy {
-            n: 1,
-            f: f0,
-            bits: Bits::new(),
+            window: Bits::new(),
+            start: 0,
+            capacity: capacity.max(1),
+            cursor: 0,
         }
     }
 
-    /// Advance the generator by one step, append one bit to the stream.
-    ///
-    /// Update rule (your e-generator interpreted iteratively):
-    ///     f_{n+1} = 1 + f_n / (n+1)
-    ///
-    /// Then we take one bit from the floating-point representation
-    /// of `f` to get a deterministic boolean.
-    fn step(&mut self) {
-        // advance n
-        self.n += 1;
-
-        // update f via your rule
-        self.f = 1.0 + self.f / (self.n as f64);
-
-        // derive a bit from the current f
-        // using its IEEE-754 bit-pattern for determinism
-        let raw = self.f.to_bits(); // u64
-        let bit = (raw & 1) == 1;
+    /// Most bits held at once.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
 
-        self.bits.push(bit);
+    /// The stream positions currently held.
+    pub fn held(&self) -> Range<usize> {
+        self.start..self.start + self.window.len()
     }
 
-    /// Ensure we have generated at least `pos + 1` bits.
-    fn ensure_pos(&mut self, pos: usize) {
-        while self.bits.len() <= pos {
-            self.step();
+    /// Bring `[from, to)` into the window, recomputing it when it does not
+    /// cover them already. `to - from` must not exceed the capacity.
+    fn load(&mut self, from: usize, to: usize) {
+        let held = self.held();
+        if held.start <= from && to <= held.end {
+            return;
         }
+
+        // Running forward: reach ahead as far again as we got, so a scan
+        // recomputes O(log n) times until the window is full.
+        let ahead = if from >= held.start { 2 * held.end } else { 0 };
+        let end = to.max(ahead).max(MIN_PRECISION).min(from + self.capacity);
+        let start = end.saturating_sub(self.capacity).min(from);
+        self.window = expansion_range(start, end);
+        self.start = start;
     }
 
     /// Get the universal bit at a given pos.
     ///
-    /// This is a lazy interface: if the internal buffer does not yet
-    /// reach `pos`, it will generate as many bits as needed.
+    /// This is a lazy interface: if the window does not hold `pos`, a
+    /// window holding it is computed.
     pub fn bit(&mut self, pos: usize) -> bool {
-        self.ensure_pos(pos);
-        self.bits[pos]
+        self.load(pos, pos + 1);
+        self.window[pos - self.start]
     }
 
     /// Get a prefix of the universal bit stream of length `n_bits`.
     pub fn bits_to_pos(&mut self, n_bits: usize) -> Bits {
-        self.ensure_pos(n_bits.saturating_sub(1));
-        self.bits[..n_bits].to_bitvec()
+        self.bits_range(0, n_bits)
     }
 
     /// Get a range [start, start + len) from the universal bit stream.
+    ///
+    /// A range longer than the capacity is computed directly; only its
+    /// tail is kept.
     pub fn bits_range(&mut self, start: usize, len: usize) -> Bits {
         if len == 0 {
             return Bits::new();
         }
-        self.ensure_pos(start + len - 1);
+        if len > self.capacity {
+            let bits = expansion_range(start, start + len);
+            self.window = bits[len - self.capacity..].to_bitvec();
+            self.start = start + len - self.capacity;
+            return bits;
+        }
+        self.load(start, start + len);
+        let at = start - self.start;
+        self.window[at..at + len].to_bitvec()
+    }
+}
+
+/// Sequential reads, from a cursor starting at position 0.
+///
+/// Bytes and words are read most significant bit first, so the bytes of
+/// [`fill_bytes`](UniversalEntropy::fill_bytes) are the stream's own bytes
+/// and [`next_u64`](UniversalEntropy::next_u64) is the next 64 bits read as
+/// a big-endian number.
+impl UniversalEntropy {
+    /// The next position the sequential reads will consume.
+    pub fn position(&self) -> usize {
+        self.cursor
+    }
+
+    /// Move the cursor to `pos`; the window is left alone.
+    pub fn seek(&mut self, pos: usize) {
+        self.cursor = pos;
+    }
+
+    /// Fill `dest` with the next `8 * dest.len()` bits.
+    pub fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let bits = self.bits_range(self.cursor, 8 * dest.len());
+        dest.view_bits_mut::<Msb0>().copy_from_bitslice(&bits);
+        self.cursor += 8 * dest.len();
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        UniversalEntropy::fill_bytes(self, &mut bytes);
+        u32::from_be_bytes(bytes)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        UniversalEntropy::fill_bytes(self, &mut bytes);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// The bits from the cursor on, advancing it as they are read.
+    pub fn iter(&mut self) -> Iter<'_> {
+        Iter { entropy: self }
+    }
+}
+
+/// Endless iterator over a [`UniversalEntropy`]'s bits from its cursor.
+#[derive(Debug)]
+pub struct Iter<'a> {
+    entropy: &'a mut UniversalEntropy,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        let bit = self.entropy.bit(self.entropy.cursor);
+        self.entropy.cursor += 1;
+        Some(bit)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
+/// The universal stream as a [`rand_core`] generator, for shuffles, jitter
+/// and sampling. Deterministic and shared by every peer: not for secrets.
+impl rand_core::RngCore for UniversalEntropy {
+    fn next_u32(&mut self) -> u32 {
+        UniversalEntropy::next_u32(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        UniversalEntropy::next_u64(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        UniversalEntropy::fill_bytes(self, dest)
+    }
+}
+
+impl EntropySource for UniversalEntropy {
+    fn bit(&mut self, pos: usize) -> bool {
+        UniversalEntropy::bit(self, pos)
+    }
+
+    fn range(&mut self, start: usize, len: usize) -> Bits {
+        self.bits_range(start, len)
+    }
+
+    /// Drop the window and rewind the cursor; the bits themselves never
+    /// change.
+    fn reset(&mut self) {
+        *self = Self::with_capacity(self.capacity);
+    }
+}
+
+/// Counter-mode hash stream: block `n` is the keyed BLAKE3 hash of the
+/// little-endian `u64` `n`, 256 bits per block.
+///
+/// Cheap at any position, and a different seed gives an unrelated stream.
+#[derive(Debug, Clone)]
+pub struct HashStream {
+    key: [u8; 32],
+    /// The most recently hashed block and its counter.
+    block: Option<(u64, [u8; 32])>,
+}
+
+impl HashStream {
+    /// A stream keyed by the hash of `seed`.
+    pub fn new(seed: &[u8]) -> Self {
+        HashStream {
+            key: *blake3::hash(seed).as_bytes(),
+            block: None,
+        }
+    }
+
+    fn block(&mut self, counter: u64) -> &[u8; 32] {
+        if !matches!(self.block, Some((at, _)) if at == counter) {
+            let hash = blake3::keyed_hash(&self.key, &counter.to_le_bytes());
+            self.block = Some((counter, *hash.as_bytes()));
+        }
+        &self.block.as_ref().expect("block just hashed").1
+    }
+}
+
+impl EntropySource for HashStream {
+    fn bit(&mut self, pos: usize) -> bool {
+        let byte = self.block((pos / 256) as u64)[pos % 256 / 8];
+        byte & (0x80 >> (pos % 8)) != 0
+    }
+
+    fn range(&mut self, start: usize, len: usize) -> Bits {
+        let mut out = Bits::with_capacity(len);
+        let mut pos = start;
+        while pos < start + len {
+            let block = Bits::from_slice(self.block((pos / 256) as u64));
+            let end = (start + len).min((pos / 256 + 1) * 256);
+            out.extend_from_bitslice(&block[pos % 256..end - pos / 256 * 256]);
+            pos = end;
+        }
+        out
+    }
+
+    fn reset(&mut self) {
+        self.block = None;
+    }
+}
+
+/// Bits from the operating system's random number generator, for testing
+/// that nothing depends on a particular stream.
+///
+/// Bits are drawn as positions are first read and kept, so a position
+/// reads the same until [`reset`](EntropySource::reset) discards them.
+#[derive(Debug, Clone, Default)]
+pub struct OsRandom {
+    bits: Bits,
+}
+
+impl OsRandom {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn draw_to(&mut self, end: usize) {
+        if end <= self.bits.len() {
+            return;
+        }
+        let mut bytes = vec![0; (end - self.bits.len()).div_ceil(8)];
+        getrandom::fill(&mut bytes).expect("operating system random number generator");
+        self.bits.extend_from_raw_slice(&bytes);
+    }
+}
+
+impl EntropySource for OsRandom {
+    fn bit(&mut self, pos: usize) -> bool {
+        self.draw_to(pos + 1);
+        self.bits[pos]
+    }
+
+    fn range(&mut self, start: usize, len: usize) -> Bits {
+        self.draw_to(start + len);
         self.bits[start..start + len].to_bitvec()
     }
+
+    fn reset(&mut self) {
+        self.bits.clear();
+    }
+}
+
+/// Wraps a source and records the prefix of it read so far, for a
+/// [`Replayed`] source to play back later.
+#[derive(Debug, Clone)]
+pub struct Recorded<S> {
+    inner: S,
+    bits: Bits,
+}
+
+impl<S: EntropySource> Recorded<S> {
+    pub fn new(inner: S) -> Self {
+        Recorded {
+            inner,
+            bits: Bits::new(),
+        }
+    }
+
+    /// Every bit up to the furthest position read.
+    pub fn recording(&self) -> &Bits {
+        &self.bits
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn record_to(&mut self, end: usize) {
+        if end > self.bits.len() {
+            let more = self.inner.range(self.bits.len(), end - self.bits.len());
+            self.bits.extend_from_bitslice(&more);
+        }
+    }
+}
+
+impl<S: EntropySource> EntropySource for Recorded<S> {
+    fn bit(&mut self, pos: usize) -> bool {
+        self.record_to(pos + 1);
+        self.bits[pos]
+    }
+
+    fn range(&mut self, start: usize, len: usize) -> Bits {
+        self.record_to(start + len);
+        self.bits[start..start + len].to_bitvec()
+    }
+
+    /// Reset the inner source and start a new recording.
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.bits.clear();
+    }
+}
+
+/// Plays back a recorded stream.
+///
+/// Panics when asked for a position past the end of the recording: the
+/// replay has diverged from the run that was recorded.
+#[derive(Debug, Clone)]
+pub struct Replayed {
+    bits: Bits,
+}
+
+impl Replayed {
+    pub fn new(bits: Bits) -> Self {
+        Replayed { bits }
+    }
+
+    fn check(&self, end: usize) {
+        if end > self.bits.len() {
+            panic!(
+                "Replayed: position {} is past the {} recorded bits.",
+                end - 1,
+                self.bits.len()
+            );
+        }
+    }
+}
+
+impl EntropySource for Replayed {
+    fn bit(&mut self, pos: usize) -> bool {
+        self.check(pos + 1);
+        self.bits[pos]
+    }
+
+    fn range(&mut self, start: usize, len: usize) -> Bits {
+        if len == 0 {
+            return Bits::new();
+        }
+        self.check(start + len);
+        self.bits[start..start + len].to_bitvec()
+    }
+
+    /// Nothing to do: a recording does not change.
+    fn reset(&mut self) {}
+}
+
+/// The first `precision` fractional bits of e^(1/e).
+pub fn expansion(precision: usize) -> Bits {
+    expansion_range(0, precision)
+}
+
+/// Fractional bits `start + 1 ..= end` of e^(1/e): positions
+/// `[start, end)` of the stream.
+pub fn expansion_range(start: usize, end: usize) -> Bits {
+    let len = end.saturating_sub(start);
+    if len == 0 {
+        return Bits::new();
+    }
+
+    let mask = (BigUint::from(1u8) << len) - 1u8;
+    let value = (fraction(end) & mask) << (len.next_multiple_of(8) - len);
+    let mut bytes = value.to_bytes_be();
+    let mut out = vec![0; len.div_ceil(8) - bytes.len()];
+    out.append(&mut bytes);
+    let mut bits = Bits::from_vec(out);
+    bits.truncate(len);
+    bits
+}
+
+/// `⌊frac(e^(1/e)) · 2^precision⌋`, exactly.
+///
+/// Works in fixed point with `w = precision + guard` bits:
+///
+/// 1. 1/e from the partial sum of `Σ 1/k!`, with enough terms that the
+///    truncation is below one unit
+/// 2. e^(1/e) by the bit-burst method: 1/e is cut into chunks of doubling
+///    width, `exp` of each chunk is summed exactly by binary splitting and
+///    the results are multiplied together
+///
+/// Every step rounds down by at most a unit or two, so the result is
+/// within a known bound `err` of the true value. When the interval
+/// `[approx - err, approx + err]` does not straddle a multiple of
+/// `2^guard` the requested bits are exact; in the (vanishingly rare) case
+/// it does, retry with more guard bits.
+fn fraction(precision: usize) -> BigUint {
+    let mut guard = 64;
+    loop {
+        let w = precision + guard;
+
+        // 1. 1/e = Q / (P + Q), where e ≈ 1 + P/Q.
+        let (p, q) = inverse_factorials(0, terms(w + 4, 0));
+        let y = (&q << w) / (p + &q);
+
+        // 2. exp(y) = Π exp(chunk) over chunks of y's bits [start, end).
+        let one = BigUint::from(1u8) << w;
+        let mut product = one.clone();
+        let mut chunks = 0;
+        let (mut start, mut end) = (0, 8);
+        while start < w {
+            let stop = end.min(w);
+            // The chunk's bits as an integer `a`, worth `a / 2^stop`.
+            let a = (&y >> (w - stop)) & ((BigUint::from(1u8) << (stop - start)) - 1u8);
+            if a != BigUint::ZERO {
+                let n = terms(w + 4, start);
+                let (_, q, t) = exp_series(&a, stop, 1, n + 1);
+                let exp = &one + (t << w) / q;
+                product = (product * exp) >> w;
+                chunks += 1;
+            }
+            (start, end) = (end, 2 * end);
+        }
+
+        let err = BigUint::from(8 * (chunks + 2u32));
+        let low = (&product - &err) >> guard;
+        let high = (&product + &err) >> guard;
+        if low == high {
+            // Drop the integer part, leaving the fractional bits.
+            return low - (BigUint::from(1u8) << precision);
+        }
+        guard += 64;
+    }
+}
+
+/// How many terms of `Σ x^k / k!`, for `0 ≤ x < 2^-scale`, make the tail
+/// smaller than `2^-bits`.
+fn terms(bits: usize, scale: usize) -> u64 {
+    let (mut k, mut log2_term) = (1u64, 0.0);
+    while log2_term < bits as f64 + 1.0 {
+        k += 1;
+        log2_term += (k as f64).log2() + scale as f64;
+    }
+    k
+}
+
+/// `(P, Q)` with `P / Q = Σ_{k=a+1}^{b} 1 / ((a+1)(a+2)⋯k)` and
+/// `Q = (a+1)(a+2)⋯b`, by binary splitting.
+fn inverse_factorials(a: u64, b: u64) -> (BigUint, BigUint) {
+    if b - a == 1 {
+        return (BigUint::from(1u8), BigUint::from(b));
+    }
+    let m = a + (b - a) / 2;
+    let (p1, q1) = inverse_factorials(a, m);
+    let (p2, q2) = inverse_factorials(m, b);
+    (p1 * &q2 + p2, q1 * q2)
+}
+
+/// `(P, Q, T)` with `T / Q = Σ_{k=a}^{b-1} Π_{j=a}^{k} x / j` for
+/// `x = num / 2^shift`, and `P = num^(b-a)`, by binary splitting.
+fn exp_series(num: &BigUint, shift: usize, a: u64, b: u64) -> (BigUint, BigUint, BigUint) {
+    if b - a == 1 {
+        return (num.clone(), BigUint::from(a) << shift, num.clone());
+    }
+    let m = a + (b - a) / 2;
+    let (p1, q1, t1) = exp_series(num, shift, a, m);
+    let (p2, q2, t2) = exp_series(num, shift, m, b);
+    (&p1 * &p2, q1 * &q2, t1 * q2 + p1 * t2)
 }
@@ -0,0 +1,46 @@
+/*
+ * `hey,`
+ *
+ * @description: A thermodynamically complete universal data layer, communicating entropical deltas.
+ * @author:
+ *     George Phillips <george.phillips@nanoly.cloud>
+ *
+ * Copyright (C) 2025
+ * GNU Affero General Public License v3 or later
+ */
+
+//! Embeddable `hey,` node.
+//!
+//! A [`Mesh`] owns everything a running node needs: the bound UDP socket,
+//! its evolving [`Node`] state, the shared [`UniversalEntropy`] stream,
+//! the set of known peers and the [`Store`] frame log.
+
+pub mod codec;
+pub mod coords;
+pub mod digest;
+pub mod entropy;
+pub mod mesh;
+pub mod node;
+pub mod patch;
+pub mod peers;
+pub mod replay;
+pub mod stats;
+pub mod store;
+pub mod sync;
+pub mod wire;
+
+pub use codec::{decode, encode};
+pub use coords::Atom;
+pub use digest::{Digest, MerkleTree};
+pub use entropy::{EntropySource, HashStream, OsRandom, Recorded, Replayed, UniversalEntropy};
+pub use mesh::{bind, to_port, Mesh, StopHandle};
+pub use node::{Bits, Node, MAX_LEAVES, ROOT, SIZE};
+pub use patch::NodePatch;
+pub use peers::{Peer, PeerTable};
+pub use replay::{replay, Replay};
+pub use stats::Report;
+pub use store::{
+    Compaction, FrameIter, Record, RecordIter, Retention, RevRecordIter, Source, Store,
+};
+pub use sync::{SyncEntry, SyncSession};
+pub use wire::Message;
@@ -9,88 +9,114 @@
  * GNU Affero General Public License v3 or later
  */
 
-use bitvec::prelude::*;
 use std::{
-    collections::HashSet,
+    env,
     io::{self, BufRead},
-    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
     sync::mpsc,
     thread,
     time::Duration,
 };
 
-mod entropy;
-mod node;
-mod store;
-
-use node::{Bits, Node, ROOT, SIZE};
-
-use crate::{entropy::UniversalEntropy, store::Store};
-
-/// Map a Node to a UDP port.
-pub fn to_port(node: &Node) -> u16 {
-    let ttl: usize = 65535 - SIZE;
-
-    let bits: Bits = node.clone().into();
-    let port = (bits.len() % ttl) + SIZE;
-    let port_u16 = port as u16;
+use hey::{
+    EntropySource, HashStream, Mesh, Node, OsRandom, Report, Retention, StopHandle, Store,
+    UniversalEntropy, ROOT,
+};
 
-    println!("[PORT] Using UDP port {}", port_u16);
-    port_u16
+const USAGE: &str = "usage: hey [--resume] [--heartbeat <secs>] [--peer-timeout <secs>]
+                [--keep-frames <n>] [--keep-bytes <n>]
+       hey replay <log>
+       hey entropy-test [--bits <n>] [--source universal|hash|os]";
+
+/// Bits tested by `hey entropy-test` by default.
+const TEST_BITS: usize = 1_000_000;
+
+/// What to do this invocation.
+#[derive(Debug)]
+enum Command {
+    /// Join the mesh (the default).
+    Run(Options),
+    /// Re-run a journaled log and verify every recorded state.
+    Replay(PathBuf),
+    /// Run the statistical test suite over an entropy stream.
+    EntropyTest { bits: usize, source: String },
 }
 
-/// Try to bind a UDP socket based on the current node state.
-pub fn bind(node: &Node) -> Result<(u16, UdpSocket), io::Error> {
-    let port = to_port(node);
-    let addr = format!("0.0.0.0:{}", port);
-
-    let socket = match UdpSocket::bind(&addr) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("[UDP ERROR] Failed to bind to {}: {}", addr, e);
-            return Err(e);
-        }
-    };
-
-    socket.set_broadcast(true)?;
-    println!("[UDP] Bound UDP socket to {}", addr);
-    Ok((port, socket))
+/// Command line options.
+#[derive(Debug, Default)]
+struct Options {
+    resume: bool,
+    heartbeat: Option<Duration>,
+    peer_timeout: Option<Duration>,
+    retention: Retention,
 }
 
-/// Once bound, sit and receive, perform minimal handshake based on ROOT,
-/// and also read from stdin, folding input bytes into the node state and
-/// sending them to all known peers.
-fn begin(mut socket: UdpSocket, port: u16, node: &mut Node) -> io::Result<()> {
-    println!(
-        "[MESH] Bound successfully on port {} with node state: {:?}",
-        port, node
-    );
+fn usage(msg: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, USAGE))
+}
 
-    socket.set_nonblocking(true)?;
+fn seconds(flag: &str, value: Option<String>) -> io::Result<Duration> {
+    let value = value.ok_or_else(|| usage(format!("{} needs a value", flag)))?;
+    let secs: f64 = value
+        .parse()
+        .map_err(|_| usage(format!("{}: not a number of seconds: {}", flag, value)))?;
+    Duration::try_from_secs_f64(secs).map_err(|e| usage(format!("{}: {}", flag, e)))
+}
 
-    // Compute the canonical "root" port from ROOT state.
-    let root_node = Node::from(BitVec::from_slice(&ROOT));
-    let root_port = to_port(&root_node);
-    let mut entropy = UniversalEntropy::new();
+fn count<T: std::str::FromStr>(flag: &str, value: Option<String>) -> io::Result<T> {
+    let value = value.ok_or_else(|| usage(format!("{} needs a value", flag)))?;
+    value
+        .parse()
+        .map_err(|_| usage(format!("{}: not a count: {}", flag, value)))
+}
 
-    // Known peers (can be many).
-    let mut peers: HashSet<SocketAddr> = HashSet::new();
+fn parse_args(mut args: impl Iterator<Item = String>) -> io::Result<Command> {
+    let mut args = args.by_ref().peekable();
+    if args.peek().map(String::as_str) == Some("replay") {
+        args.next();
+        let log = args
+            .next()
+            .ok_or_else(|| usage("replay needs a log path"))?;
+        if let Some(extra) = args.next() {
+            return Err(usage(format!("unexpected argument: {}", extra)));
+        }
+        return Ok(Command::Replay(log.into()));
+    }
+    if args.peek().map(String::as_str) == Some("entropy-test") {
+        args.next();
+        let (mut bits, mut source) = (TEST_BITS, String::from("universal"));
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bits" => bits = count(&arg, args.next())?,
+                "--source" => {
+                    source = args.next().ok_or_else(|| usage("--source needs a value"))?
+                }
+                other => return Err(usage(format!("unknown argument: {}", other))),
+            }
+        }
+        return Ok(Command::EntropyTest { bits, source });
+    }
 
-    // If we are NOT the root node, announce ourselves to the root port.
-    if port != root_port {
-        let bits: Bits = node.clone().into();
-        let buf = bits.into_vec();
-        let target = format!("127.0.0.1:{}", root_port);
-        println!("[HANDSHAKE] Announcing to {}", target);
-        let _ = socket.send_to(&buf, &target)?;
-    } else {
-        println!(
-            "[HANDSHAKE] This node is the ROOT node (port {}).",
-            root_port
-        );
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--resume" => options.resume = true,
+            "--heartbeat" => options.heartbeat = Some(seconds(&arg, args.next())?),
+            "--peer-timeout" => options.peer_timeout = Some(seconds(&arg, args.next())?),
+            "--keep-frames" => options.retention.frames = Some(count(&arg, args.next())?),
+            "--keep-bytes" => options.retention.bytes = Some(count(&arg, args.next())?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => return Err(usage(format!("unknown argument: {}", other))),
+        }
     }
+    Ok(Command::Run(options))
+}
 
-    // === stdin reader thread ===
+/// Read stdin line by line on its own thread, handing each line to the mesh.
+fn spawn_stdin() -> mpsc::Receiver<Vec<u8>> {
     let (tx, rx) = mpsc::channel::<Vec<u8>>();
     thread::spawn(move || {
         println!("[STDIN] stdin thread started; type and press ENTER.");
@@ -119,91 +145,90 @@ fn begin(mut socket: UdpSocket, port: u16, node: &mut Node) -> io::Result<()> {
             }
         }
     });
+    rx
+}
 
-    let mut buf = [0u8; SIZE];
-
-    loop {
-        // === 1. Network side: UDP receive / handshake / chat ===
-        match socket.recv_from(&mut buf) {
-            Ok((n, src)) => {
-                // hey, is our explicit handshake ack
-                let result = &buf[..n];
-                if n == 5 && result == ROOT {
-                    println!("[HANDSHAKE] Received {} from {}", result.len(), src);
-                    if peers.insert(src) {
-                        println!("[HANDSHAKE] Added new peer {}", src);
-                    }
-                    continue;
-                }
-
-                println!(
-                    "[NET] Received {} bytes from {}: {:?}",
-                    n,
-                    src,
-                    String::from_utf8_lossy(&buf[..n])
-                );
+/// `hey replay <log>`: re-run the journal and report any divergence.
+fn replay(path: PathBuf) -> io::Result<()> {
+    if !path.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no log at {}", path.display()),
+        ));
+    }
 
-                // Track every sender as a peer.
-                if peers.insert(src) {
-                    println!("[HANDSHAKE] Learned new peer addr = {}", src);
-                }
+    let mut store = Store::open(&path)?;
+    let report = hey::replay(&mut store)?;
 
-                // Fold payload into Node state (optional, but matches your model).
-                let payload = Node::from(BitVec::from_slice(&buf[..n]));
+    println!(
+        "[REPLAY] {}: {} records, {} snapshots, {} inputs, {} states verified",
+        path.display(),
+        report.records,
+        report.snapshots,
+        report.inputs,
+        report.verified
+    );
+    if let Some(node) = &report.node {
+        println!("[REPLAY] Final identity {}", node.fingerprint());
+    }
+    for divergence in &report.divergences {
+        println!(
+            "[REPLAY] record {}: recorded {} leaves, replayed {} leaves",
+            divergence.record,
+            divergence.expected.size(),
+            divergence.replayed.size()
+        );
+    }
 
-                *node = node.reflect(&mut entropy, &payload);
-                println!("[MESH] Updated node state from peer: {:?}", node);
+    if report.is_consistent() {
+        println!("[REPLAY] OK: every recorded state matches bit-for-bit.");
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} divergent and {} unreadable records",
+                report.divergences.len(),
+                report.unreadable
+            ),
+        ))
+    }
+}
 
-                // If we are the ROOT node and this looks like an announcement,
-                // respond with HELLO so the sender learns us as a peer.
-                if port == root_port {
-                    println!("[MSG] {} {}", src, String::from_utf8_lossy(&ROOT));
-                    let _ = socket.send_to(ROOT, src)?;
-                }
-            }
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                // No UDP this tick — that’s fine.
-            }
-            Err(e) => {
-                eprintln!("[MESH ERROR] UDP receive error: {}", e);
-                return Err(e);
-            }
-        }
+/// `hey entropy-test`: run the statistical suite over `bits` of a stream.
+fn entropy_test(bits: usize, source: &str) -> io::Result<()> {
+    let mut source: Box<dyn EntropySource> = match source {
+        "universal" => Box::new(UniversalEntropy::new()),
+        "hash" => Box::new(HashStream::new(ROOT)),
+        "os" => Box::new(OsRandom::new()),
+        other => return Err(usage(format!("unknown entropy source: {}", other))),
+    };
+    if bits < 1000 {
+        return Err(usage("--bits: test at least 1000 bits"));
+    }
 
-        // === 2. Local side: stdin input → node state → send to all peers ===
-        match rx.try_recv() {
-            Ok(data) => {
-                println!(
-                    "[STDIN] Got {} bytes: {:?}",
-                    data.len(),
-                    String::from_utf8_lossy(&data)
-                );
-
-                // Fold stdin bytes into the evolving entropical state.
-                let input_bits: Bits = BitVec::from_slice(&data);
-                let input_node: Node = Node::from(input_bits);
-                *node = node.reflect(&mut entropy, &input_node);
-                println!("[MESH] Updated node state from stdin: {:?}", node);
-
-                if peers.is_empty() {
-                    println!("[CHAT] No peers known yet; not sending.");
-                } else {
-                    for peer in &peers {
-                        println!("[CHAT] Sending {} bytes to {}", data.len(), peer);
-                        let _ = socket.send_to(&data, peer)?;
-                    }
-                }
-            }
-            Err(mpsc::TryRecvError::Empty) => {
-                // no stdin this tick
-            }
-            Err(mpsc::TryRecvError::Disconnected) => {
-                // stdin thread ended; just keep serving network
-            }
-        }
+    let report = Report::of_source(source.as_mut(), bits);
+    println!(
+        "[ENTROPY] {} bits, fingerprint {}",
+        report.bits,
+        source.fingerprint().short()
+    );
+    for outcome in &report.outcomes {
+        println!("[ENTROPY] {}", outcome);
+    }
 
-        // Small sleep so we don't busy-spin.
-        thread::sleep(Duration::from_millis(20));
+    let failed = report.failures().count();
+    if failed == 0 {
+        println!(
+            "[ENTROPY] OK: every test passed at α = {}.",
+            hey::stats::ALPHA
+        );
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} of {} tests failed", failed, report.outcomes.len()),
+        ))
     }
 }
 
@@ -211,35 +236,48 @@ fn begin(mut socket: UdpSocket, port: u16, node: &mut Node) -> io::Result<()> {
 /// Self discovering UDP network, communicating an evolving Node state
 /// Note: how ROOT = 'hey'
 fn main() -> io::Result<()> {
-    println!("=== {} - (AGPLv3) ===\n", String::from_utf8_lossy(&ROOT));
+    let options = match parse_args(env::args().skip(1))? {
+        Command::Run(options) => options,
+        Command::Replay(path) => return replay(path),
+        Command::EntropyTest { bits, source } => return entropy_test(bits, &source),
+    };
 
-    let mut entropy = UniversalEntropy::new();
+    println!("=== {} - (AGPLv3) ===\n", String::from_utf8_lossy(ROOT));
 
     let mut frames = Store::open("state")?;
+    if !options.retention.is_unbounded() {
+        frames.compact(options.retention)?;
+    }
 
-    // Initial entropical state from ROOT.
-    let mut node = Node::from(BitVec::from_slice(&ROOT));
-
-    loop {
-        frames.append_frame(&node)?;
-
-        // Attempt to bind using current node state.
-        match bind(&node) {
-            Ok((port, socket)) => {
-                // Success: we have found this node’s place in the local mesh.
-                // From here on, we just participate; main never returns.
-                return begin(socket, port, &mut node);
-            }
-            Err(ref e) if e.kind() == io::ErrorKind::AddrInUse => {
-                println!("[MESH] Port in use – encoding failure bit and hopping…");
-                let bit = Node::Bit(true);
-                node = node.reflect(&mut entropy, &bit);
-            }
-            Err(e) => {
-                // Any other error: also evolve and keep going.
-                eprintln!("[MESH ERROR] {}", e);
-                node = node.reflect(&mut entropy, &Node::Bit(false));
-            }
-        }
+    // SIGINT / SIGTERM: stop binding or leave the run loop, so the final
+    // state is saved either way.
+    let stop = StopHandle::default();
+    let handler = stop.clone();
+    ctrlc::set_handler(move || {
+        println!("[MESH] Signal received, shutting down…");
+        handler.stop();
+    })
+    .map_err(io::Error::other)?;
+
+    let started = if options.resume {
+        // Rejoin as whoever we were when the log was last written.
+        Mesh::resume_with(frames, stop)
+    } else {
+        // Initial entropical state from ROOT.
+        Mesh::start_with(Node::root(), frames, stop)
+    };
+    let mut mesh = match started {
+        Ok(mesh) => mesh,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if let Some(every) = options.heartbeat {
+        mesh.set_heartbeat(every);
     }
+    if let Some(timeout) = options.peer_timeout {
+        mesh.set_peer_timeout(timeout);
+    }
+    mesh.set_retention(options.retention);
+
+    mesh.run(spawn_stdin())
 }
@@ -0,0 +1,647 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    digest::{Digest, MerkleTree},
+    entropy::UniversalEntropy,
+    node::{Bits, Node, MAX_LEAVES, SIZE},
+    peers::{PeerTable, HEARTBEAT},
+    store::{Retention, Source, Store},
+    sync::{self, SyncSession},
+    wire::{Message, MAX_DATAGRAM},
+};
+
+/// How long a sync request may go unanswered before it is sent again.
+pub const SYNC_RETRY: Duration = Duration::from_secs(1);
+
+/// Map a Node to a UDP port.
+pub fn to_port(node: &Node) -> u16 {
+    let ttl: usize = MAX_LEAVES;
+
+    let bits: Bits = node.into();
+    let port = (bits.len() % ttl) + SIZE;
+    let port_u16 = port as u16;
+
+    println!("[PORT] Using UDP port {}", port_u16);
+    port_u16
+}
+
+/// Try to bind a UDP socket based on the current node state.
+pub fn bind(node: &Node) -> Result<(u16, UdpSocket), io::Error> {
+    let port = to_port(node);
+    let addr = format!("0.0.0.0:{}", port);
+
+    let socket = match UdpSocket::bind(&addr) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("[UDP ERROR] Failed to bind to {}: {}", addr, e);
+            return Err(e);
+        }
+    };
+
+    socket.set_broadcast(true)?;
+    println!("[UDP] Bound UDP socket to {}", addr);
+    Ok((port, socket))
+}
+
+/// Cloneable handle used to ask a running [`Mesh`] to stop.
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    /// Ask the mesh to leave its run loop on the next tick.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// A bound `hey,` node participating in the local mesh.
+///
+/// Owns the UDP socket, the evolving Node state, the universal entropy
+/// stream, the known peers and the frame log.
+pub struct Mesh {
+    socket: UdpSocket,
+    port: u16,
+    root_port: u16,
+    node: Node,
+    entropy: UniversalEntropy,
+    peers: PeerTable,
+    heartbeat: Duration,
+    last_heartbeat: Instant,
+    nonce: u64,
+    store: Store,
+    retention: Retention,
+    stop: StopHandle,
+    /// Digests of `node`'s subtrees, built when a peer first asks for them.
+    merkle: Option<MerkleTree>,
+    /// In-flight copies of peer states, with when each last asked.
+    sessions: HashMap<SocketAddr, (SyncSession, Instant)>,
+}
+
+impl Mesh {
+    /// Find this node's place in the local mesh, starting from `node`.
+    ///
+    /// `node` is journaled as a snapshot, then every bind attempt is
+    /// appended to `store` as a frame; a failed bind is journaled as an
+    /// input and folded back into the node state before hopping to the
+    /// next port.
+    pub fn start(node: Node, store: Store) -> io::Result<Self> {
+        Mesh::start_with(node, store, StopHandle::default())
+    }
+
+    /// [`Mesh::start`], giving up the search once `stop` is stopped: the
+    /// frames written so far are fsynced and `Interrupted` is returned.
+    ///
+    /// `stop` then also stops the started mesh's [`Mesh::run`].
+    pub fn start_with(mut node: Node, mut store: Store, stop: StopHandle) -> io::Result<Self> {
+        let mut entropy = UniversalEntropy::new();
+
+        store.append_snapshot(&node)?;
+        loop {
+            if stop.is_stopped() {
+                store.sync()?;
+                println!("[MESH] Stopped while binding; node state checkpointed.");
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "stopped before binding",
+                ));
+            }
+
+            // Attempt to bind using current node state.
+            match bind(&node) {
+                Ok((port, socket)) => {
+                    // Success: we have found this node’s place in the local mesh.
+                    socket.set_nonblocking(true)?;
+
+                    // Compute the canonical "root" port from ROOT state.
+                    let root_port = to_port(&Node::root());
+
+                    println!(
+                        "[MESH] Bound successfully on port {} as {} ({} leaves)",
+                        port,
+                        node.fingerprint().short(),
+                        node.size()
+                    );
+
+                    return Ok(Mesh {
+                        socket,
+                        port,
+                        root_port,
+                        node,
+                        entropy,
+                        peers: PeerTable::default(),
+                        heartbeat: HEARTBEAT,
+                        last_heartbeat: Instant::now(),
+                        nonce: 0,
+                        store,
+                        retention: Retention::default(),
+                        stop,
+                        merkle: None,
+                        sessions: HashMap::new(),
+                    });
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AddrInUse => {
+                    println!("[MESH] Port in use – encoding failure bit and hopping…");
+                    let bit = Node::Bit(true);
+                    store.append_input(Source::AddrInUse, &bit)?;
+                    node = node.reflect(&mut entropy, &bit);
+                }
+                Err(e) => {
+                    // Any other error: also evolve and keep going.
+                    eprintln!("[MESH ERROR] {}", e);
+                    let bit = Node::Bit(false);
+                    store.append_input(Source::BindError, &bit)?;
+                    node = node.reflect(&mut entropy, &bit);
+                }
+            }
+            store.append_frame(&node)?;
+        }
+    }
+
+    /// Restart as the node last checkpointed in `store`, falling back to
+    /// ROOT when the log holds no readable frame.
+    pub fn resume(store: Store) -> io::Result<Self> {
+        Mesh::resume_with(store, StopHandle::default())
+    }
+
+    /// [`Mesh::resume`], stopping like [`Mesh::start_with`].
+    pub fn resume_with(mut store: Store, stop: StopHandle) -> io::Result<Self> {
+        let node = match store.last_state()? {
+            Some(node) => {
+                println!(
+                    "[RESUME] Resuming from last frame ({} leaves, port {})",
+                    node.size(),
+                    to_port(&node)
+                );
+                node
+            }
+            None => {
+                println!("[RESUME] No frames in the log; starting from ROOT.");
+                Node::root()
+            }
+        };
+        Mesh::start_with(node, store, stop)
+    }
+
+    /// The UDP port this node is bound to.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The port derived from the shared ROOT state.
+    pub fn root_port(&self) -> u16 {
+        self.root_port
+    }
+
+    /// Whether this node landed on the ROOT port.
+    pub fn is_root(&self) -> bool {
+        self.port == self.root_port
+    }
+
+    /// The current evolved node state.
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// This node's identity: the Merkle root digest of its state.
+    pub fn identity(&self) -> Digest {
+        self.node.fingerprint()
+    }
+
+    /// Peers this node has heard from recently.
+    pub fn peers(&self) -> &PeerTable {
+        &self.peers
+    }
+
+    /// The latest verified copy of `peer`'s state, if we have one.
+    pub fn peer_state(&self, peer: &SocketAddr) -> Option<&Node> {
+        self.peers.get(peer)?.state.as_ref()
+    }
+
+    /// How often known peers are pinged.
+    pub fn set_heartbeat(&mut self, every: Duration) {
+        self.heartbeat = every;
+    }
+
+    /// How long a peer may stay silent before it is evicted.
+    pub fn set_peer_timeout(&mut self, timeout: Duration) {
+        self.peers.set_timeout(timeout);
+    }
+
+    /// The frame log backing this node.
+    pub fn store(&mut self) -> &mut Store {
+        &mut self.store
+    }
+
+    /// How much of the frame log to keep when compacting at shutdown.
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+    }
+
+    /// A handle that can stop [`Mesh::run`] from another thread.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Frame and send a single message.
+    ///
+    /// A message too large for one datagram is refused with `InvalidInput`
+    /// rather than sent cut short.
+    pub fn send(&mut self, message: &Message, to: SocketAddr) -> io::Result<()> {
+        let packet = message.encode();
+        if packet.len() > MAX_DATAGRAM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} byte packet is over the {} byte datagram limit",
+                    packet.len(),
+                    MAX_DATAGRAM
+                ),
+            ));
+        }
+        let _ = self.socket.send_to(&packet, to)?;
+        self.peers.sent(to);
+        Ok(())
+    }
+
+    /// If we are NOT the root node, announce ourselves to the root port.
+    pub fn announce(&mut self) -> io::Result<()> {
+        if self.is_root() {
+            println!(
+                "[HANDSHAKE] This node is the ROOT node (port {}).",
+                self.root_port
+            );
+            return Ok(());
+        }
+
+        let target = SocketAddr::from(([127, 0, 0, 1], self.root_port));
+        println!("[HANDSHAKE] Announcing to {}", target);
+        let announce = Message::Announce(self.node.clone());
+        self.send(&announce, target)
+    }
+
+    /// Receive and handle at most one datagram.
+    ///
+    /// Malformed packets are rejected without touching the node state.
+    /// Returns `Ok(false)` when nothing was waiting on the socket.
+    pub fn poll(&mut self) -> io::Result<bool> {
+        let mut buf = [0u8; MAX_DATAGRAM];
+
+        let (n, src) = match self.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                // No UDP this tick — that’s fine.
+                return Ok(false);
+            }
+            Err(e) => {
+                eprintln!("[MESH ERROR] UDP receive error: {}", e);
+                return Err(e);
+            }
+        };
+
+        let message = match Message::decode(&buf[..n]) {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("[NET] Rejected {} byte packet from {}: {}", n, src, e);
+                return Ok(true);
+            }
+        };
+
+        self.handle(message, src)?;
+        Ok(true)
+    }
+
+    /// React to a well-formed message from `src`.
+    fn handle(&mut self, message: Message, src: SocketAddr) -> io::Result<()> {
+        if let Message::Bye = message {
+            self.sessions.remove(&src);
+            if self.peers.remove(&src).is_some() {
+                println!("[PEER] Peer {} said BYE", src);
+            }
+            return Ok(());
+        }
+
+        // Track every other sender as a peer.
+        let now = Instant::now();
+        if self.peers.seen(src, now) {
+            println!("[HANDSHAKE] Learned new peer addr = {}", src);
+        }
+
+        match message {
+            Message::Announce(state) => {
+                println!("[HANDSHAKE] Announce from {}", src);
+                if state == self.node {
+                    println!("[HANDSHAKE] {} has converged on our state", src);
+                }
+                self.peers.set_state(src, state.clone());
+                self.fold(&state, Source::Peer(src))?;
+                self.send(&Message::Ack, src)?;
+            }
+            Message::Ack => {
+                println!("[HANDSHAKE] Ack from {}", src);
+            }
+            Message::Chat(data) => {
+                println!(
+                    "[NET] Chat of {} bytes from {}: {:?}",
+                    data.len(),
+                    src,
+                    String::from_utf8_lossy(&data)
+                );
+                self.fold(&Node::from(Bits::from_slice(&data)), Source::Peer(src))?;
+            }
+            Message::StateDelta(patch) => {
+                println!("[NET] State delta of {} changes from {}", patch.len(), src);
+                // Patch our copy of the sender's state and fold the result in
+                // once, however many changes the patch carries.
+                let Some(base) = self.peer_state(&src).cloned() else {
+                    println!("[NET] No state known for {}; waiting for its root.", src);
+                    return Ok(());
+                };
+                match patch.apply_to(base) {
+                    Ok(patched) => {
+                        self.peers.set_state(src, patched.clone());
+                        self.fold(&patched, Source::Peer(src))?;
+                    }
+                    Err(e) => eprintln!("[NET] Dropping state delta from {}: {}", src, e),
+                }
+            }
+            Message::Ping(nonce) => self.send(&Message::Pong(nonce), src)?,
+            Message::Pong(nonce) => {
+                self.peers.pong(src, nonce, now);
+            }
+            Message::Bye => {}
+            Message::Root(root) => self.sync_from(src, root, now)?,
+            Message::SyncRequest { root, paths } => {
+                let tree = self
+                    .merkle
+                    .get_or_insert_with(|| MerkleTree::new(&self.node));
+                if root != tree.root() {
+                    // Our state moved on: point the peer at the new one.
+                    let current = Message::Root(tree.root());
+                    return self.send(&current, src);
+                }
+                let entries = match sync::answer(&self.node, tree, &paths) {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        eprintln!("[SYNC] Refusing request from {}: {}", src, e);
+                        return Ok(());
+                    }
+                };
+                for reply in Message::sync_replies(root, entries) {
+                    self.send(&reply, src)?;
+                }
+            }
+            Message::SyncReply { root, entries } => {
+                let Some((session, _)) = self.sessions.get_mut(&src) else {
+                    return Ok(());
+                };
+                if session.root() != root {
+                    return Ok(());
+                }
+                for (path, entry) in entries {
+                    if let Err(e) = session.receive(path, entry) {
+                        eprintln!("[SYNC] Dropping sync with {}: {}", src, e);
+                        self.sessions.remove(&src);
+                        return Ok(());
+                    }
+                }
+                self.advance_sync(src, now)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start copying `src`'s state with digest `root`, unless we already
+    /// hold it or are already copying it.
+    fn sync_from(&mut self, src: SocketAddr, root: Digest, now: Instant) -> io::Result<()> {
+        let peer = self.peers.get(&src);
+        if peer.and_then(|peer| peer.state_digest()) == Some(root) {
+            return Ok(());
+        }
+        if matches!(self.sessions.get(&src), Some((session, _)) if session.root() == root) {
+            return Ok(());
+        }
+
+        // Reuse whatever we already hold: our own state and the old copy.
+        let mut sources = vec![self.node.clone()];
+        sources.extend(peer.and_then(|peer| peer.state.clone()));
+        println!("[SYNC] Syncing {} from {}", root.short(), src);
+        self.sessions
+            .insert(src, (SyncSession::new(root, sources), now));
+        self.advance_sync(src, now)
+    }
+
+    /// Send the next request of the sync with `src`, or finish it.
+    fn advance_sync(&mut self, src: SocketAddr, now: Instant) -> io::Result<()> {
+        let Some((session, asked)) = self.sessions.get_mut(&src) else {
+            return Ok(());
+        };
+
+        if !session.is_complete() {
+            let paths = session.request();
+            if paths.is_empty() {
+                return Ok(());
+            }
+            *asked = now;
+            let request = Message::SyncRequest {
+                root: session.root(),
+                paths,
+            };
+            return self.send(&request, src);
+        }
+
+        let (session, _) = self.sessions.remove(&src).expect("session exists");
+        let fetched = session.fetched();
+        match session.finish() {
+            Some(state) => {
+                println!(
+                    "[SYNC] Verified copy of {} from {} ({} leaves, {} fetched)",
+                    state.fingerprint().short(),
+                    src,
+                    state.size(),
+                    fetched
+                );
+                self.peers.set_state(src, state);
+            }
+            None => eprintln!("[SYNC] Copy from {} did not verify", src),
+        }
+        Ok(())
+    }
+
+    /// Fold a Node into the evolving entropical state, journaling the
+    /// input and the resulting state so the step can be replayed.
+    fn fold(&mut self, input: &Node, source: Source) -> io::Result<()> {
+        self.store.append_input(source, input)?;
+        self.node = self.node.reflect(&mut self.entropy, input);
+        self.merkle = None;
+        self.store.append_frame(&self.node)?;
+        println!(
+            "[MESH] Updated node state from {}: now {} ({} leaves)",
+            source,
+            self.identity().short(),
+            self.node.size()
+        );
+        Ok(())
+    }
+
+    /// Fold local input into the node state and send the resulting
+    /// state delta to all known peers, or our new root when the delta is
+    /// too large for a datagram.
+    pub fn input(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // Fold input bytes into the evolving entropical state.
+        let before = self.node.clone();
+        self.fold(&Node::from(Bits::from_slice(data)), Source::Stdin)?;
+
+        if self.peers.is_empty() {
+            println!("[DELTA] No peers known yet; not sending.");
+        } else {
+            let delta = Message::state_delta(before.diff(&self.node), self.identity());
+            if let Message::Root(root) = delta {
+                println!(
+                    "[DELTA] Delta too large; advertising {} instead.",
+                    root.short()
+                );
+            }
+            for peer in self.peers.addrs() {
+                println!("[DELTA] Sending state delta to {}", peer);
+                self.send(&delta, peer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ping peers and advertise our root when a heartbeat is due, resend
+    /// stalled sync requests and evict the silent peers.
+    pub fn tick(&mut self, now: Instant) -> io::Result<()> {
+        for peer in self.peers.expire(now) {
+            self.sessions.remove(&peer.addr);
+            println!(
+                "[PEER] Evicted {} after {:.1}s of silence ({} received, {} sent)",
+                peer.addr,
+                peer.silence(now).as_secs_f64(),
+                peer.received,
+                peer.sent
+            );
+        }
+
+        let stalled: Vec<SocketAddr> = self
+            .sessions
+            .iter()
+            .filter(|(_, (_, asked))| now.saturating_duration_since(*asked) >= SYNC_RETRY)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for peer in stalled {
+            if let Some((session, _)) = self.sessions.get_mut(&peer) {
+                session.retry();
+            }
+            self.advance_sync(peer, now)?;
+        }
+
+        if now.saturating_duration_since(self.last_heartbeat) < self.heartbeat {
+            return Ok(());
+        }
+        self.last_heartbeat = now;
+
+        let root = Message::Root(self.identity());
+        for peer in self.peers.addrs() {
+            self.nonce = self.nonce.wrapping_add(1);
+            self.peers.ping(peer, self.nonce, now);
+            self.send(&Message::Ping(self.nonce), peer)?;
+            self.send(&root, peer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Leave the mesh: say BYE to every known peer, then checkpoint the
+    /// final node state into the frame log and fsync it.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        for peer in self.peers.addrs() {
+            println!("[MESH] Sending BYE to {}", peer);
+            if let Err(e) = self.send(&Message::Bye, peer) {
+                eprintln!("[MESH ERROR] BYE to {} failed: {}", peer, e);
+            }
+        }
+
+        self.store.append_frame(&self.node)?;
+        self.store.sync()?;
+        println!("[MESH] Final node state checkpointed.");
+
+        if !self.retention.is_unbounded() {
+            self.store.compact(self.retention)?;
+        }
+        Ok(())
+    }
+
+    /// Announce, then sit and receive, folding network and local `input`
+    /// into the node state until stopped through a [`StopHandle`], then
+    /// [`shutdown`](Mesh::shutdown).
+    ///
+    /// An error also shuts down before it is returned.
+    pub fn run(&mut self, input: mpsc::Receiver<Vec<u8>>) -> io::Result<()> {
+        if let Err(e) = self.serve(input) {
+            eprintln!("[MESH ERROR] {}; shutting down.", e);
+            if let Err(shutdown) = self.shutdown() {
+                eprintln!("[MESH ERROR] Shutdown failed: {}", shutdown);
+            }
+            return Err(e);
+        }
+        self.shutdown()
+    }
+
+    /// The body of [`Mesh::run`], up to shutdown.
+    fn serve(&mut self, input: mpsc::Receiver<Vec<u8>>) -> io::Result<()> {
+        self.announce()?;
+
+        while !self.stop.is_stopped() {
+            // === 1. Network side: UDP receive / handshake / chat ===
+            self.poll()?;
+
+            // === 2. Local side: input → node state → send to all peers ===
+            match input.try_recv() {
+                Ok(data) => {
+                    println!(
+                        "[STDIN] Got {} bytes: {:?}",
+                        data.len(),
+                        String::from_utf8_lossy(&data)
+                    );
+                    self.input(&data)?;
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    // no input this tick
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    // input ended; just keep serving network
+                }
+            }
+
+            // === 3. Liveness: heartbeats and eviction ===
+            self.tick(Instant::now())?;
+
+            // Small sleep so we don't busy-spin.
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        println!("[MESH] Stopped on port {}.", self.port);
+        Ok(())
+    }
+}
@@ -19,18 +19,30 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  **/
 use bitvec::prelude::*;
-use std::net::SocketAddr;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    io,
+    net::SocketAddr,
+    sync::{Arc, LazyLock, Mutex, OnceLock, PoisonError, Weak},
+};
 
-use crate::entropy::UniversalEntropy;
+use crate::{
+    digest::Digest,
+    entropy::{EntropySource, UniversalEntropy},
+    patch::NodePatch,
+};
 
 pub const ROOT: &[u8] = b"hey";
 
 /// CHUNK_SIZE for reading from stdin and UDP socket.
 pub const SIZE: usize = 4096;
 
-/// A leaf encodes as an 8-byte block of all zeros or all ones.
-const ZERO_BLOCK: &[u8] = &[0u8; 1];
-const ONE_BLOCK: &[u8] = &[0xFFu8; 1];
+/// Most leaves a node keeps: one per port [`to_port`](crate::to_port) can
+/// hand out, so growing any further would only revisit ports.
+pub const MAX_LEAVES: usize = 65535 - SIZE;
 
 /// Bit-level buffer type used throughout.
 pub type Bits = BitVec<u8, Msb0>;
@@ -38,38 +50,112 @@ pub type Bits = BitVec<u8, Msb0>;
 /// - Bit(Zero/One): terminal leaf
 /// - Compound(left, right): internal Node
 /// - A compound is : C = [[1, [...A]], [1, [1, ...B]]]
+///
+/// Compounds are interned, never copied: building the same two children
+/// twice yields the same compound. Cloning a Node of any size is O(1),
+/// each distinct subtree is stored once however many trees hold it, and
+/// equal trees are the same compound, so comparing them is O(1) too.
 #[derive(Debug, Clone)]
 pub enum Node {
-    Compound(Box<(Node, Node)>),
+    Compound(Arc<Compound>),
     Bit(bool),
 }
 
+/// The two children of a compound Node, with its leaf count, parity and
+/// digest cached so none of them needs a walk of the tree.
+pub struct Compound {
+    children: (Node, Node),
+    leaves: usize,
+    parity: bool,
+    digest: OnceLock<Digest>,
+}
+
+impl Compound {
+    pub fn left(&self) -> &Node {
+        &self.children.0
+    }
+
+    pub fn right(&self) -> &Node {
+        &self.children.1
+    }
+
+    pub fn children(&self) -> (&Node, &Node) {
+        (&self.children.0, &self.children.1)
+    }
+}
+
+impl fmt::Debug for Compound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.children.fmt(f)
+    }
+}
+
 impl Node {
-    pub fn fold(&self, entropy: &mut UniversalEntropy, pos: usize) -> bool {
-        let expected = entropy.bit(pos);
-        let bit = match self {
+    /// The shared initial state every node starts from: the bits of ROOT.
+    pub fn root() -> Node {
+        Node::from(Bits::from_slice(ROOT))
+    }
+
+    /// The compound of `left` and `right`: the one already built from
+    /// these two children while it is still alive, or a new one.
+    pub fn compound(left: Node, right: Node) -> Node {
+        let key = (Interner::key(&left), Interner::key(&right));
+        let mut interner = INTERNER.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(shared) = interner.built.get(&key).and_then(Weak::upgrade) {
+            // Release the lock before `left` and `right` are dropped.
+            drop(interner);
+            return Node::Compound(shared);
+        }
+
+        let shared = Arc::new(Compound {
+            leaves: left.size() + right.size(),
+            parity: left.parity() ^ right.parity(),
+            children: (left, right),
+            digest: OnceLock::new(),
+        });
+        interner.insert(key, &shared);
+        Node::Compound(shared)
+    }
+
+    /// Fold the tree into a single bit at `pos`.
+    ///
+    /// Recursively, a leaf folds to `b ^ e(pos)` and a compound to
+    /// `left ^ right ^ e(pos)`. A tree of `n` leaves has `n - 1` compounds,
+    /// so `e(pos)` is XORed in `2n - 1` times, an odd number: the fold is
+    /// the parity of the leaves XOR `e(pos)`, whatever the shape.
+    pub fn fold(&self, entropy: &mut (impl EntropySource + ?Sized), pos: usize) -> bool {
+        self.parity() ^ entropy.bit(pos)
+    }
+
+    /// XOR of every leaf, cached on each compound.
+    pub fn parity(&self) -> bool {
+        match self {
             Node::Bit(b) => *b,
-            Node::Compound(compound) => {
-                let (left, right) = compound.as_ref();
-                let left_bit = left.fold(entropy, pos);
-                let right_bit = right.fold(entropy, pos);
-                left_bit ^ right_bit
-            }
-        };
-        bit ^ expected
+            Node::Compound(compound) => compound.parity,
+        }
     }
 
-    pub fn reflect<'input>(&mut self, entropy: &mut UniversalEntropy, input: &'input Node) -> Node {
+    pub fn reflect(&mut self, entropy: &mut (impl EntropySource + ?Sized), input: &Node) -> Node {
         // let branch = Node::Compound::(self, input);
-        let branch = self.op(entropy, input).into();
-        let node = match branch {
-            Node::Bit(bit) => Node::Compound(Box::new((Node::Bit(bit), branch.into()))),
-            Node::Compound(compound) => {
-                let (left, right) = *compound;
-                Node::Compound(Box::new((left.into(), right.into())))
-            }
-        };
-        node
+        let mut leaves = self.op(entropy, input);
+
+        // Seal the result with its own fold, so every reflection grows the
+        // tree by one leaf (and moves the node's port).
+        seal(entropy, &mut leaves);
+        Node::canonical_from(leaves)
+    }
+
+    /// The canonical form of this node: its leaves, folded down to at most
+    /// [`MAX_LEAVES`], in the balanced shape `Node::from(Bits)` builds.
+    ///
+    /// Every peer computes the same canonical form for the same leaves, and
+    /// it keeps the leaf parity, and so every [`fold`](Node::fold), intact.
+    pub fn canonical(&self) -> Node {
+        Node::canonical_from(Bits::from(self))
+    }
+
+    fn canonical_from(leaves: Bits) -> Node {
+        Node::from(bound_leaves(leaves))
     }
 
     // pub fn op(&self, entropy: &mut UniversalEntropy, other: &Node) -> Bits {
@@ -80,79 +166,301 @@ impl Node {
     //     result
     // }
     /// Perform a three-way XOR between two Nodes & their expected entropical value.
-    pub fn op(&self, entropy: &mut UniversalEntropy, other: &Node) -> Bits {
+    ///
+    /// Each position is `fold(self) ^ fold(other) ^ e(pos)`. The fold of a
+    /// tree only depends on `pos` through `e(pos)` (see [`Node::fold`]), so
+    /// both trees are traversed once and every position costs O(1).
+    pub fn op(&self, entropy: &mut (impl EntropySource + ?Sized), other: &Node) -> Bits {
         let size = self.size().max(other.size());
-        let mut result: Bits = BitVec::with_capacity(size);
+        op_bits(entropy, size, self.parity() ^ other.parity())
+    }
+
+    /// The patch turning this node into `other`: the subtrees of `other`
+    /// that differ, addressed by their path from the root.
+    pub fn diff(&self, other: &Node) -> NodePatch {
+        NodePatch::between(self, other)
+    }
+
+    /// Apply `patch` to this node, sharing every subtree it leaves alone.
+    /// On error the node is left unchanged.
+    pub fn apply(&mut self, patch: &NodePatch) -> io::Result<()> {
+        *self = patch.apply_to(self.clone())?;
+        Ok(())
+    }
 
-        for pos in 0..size {
-            let a_bit = self.fold(entropy, pos);
-            let b_bit = other.fold(entropy, pos);
-            let expected = entropy.bit(pos);
-            let res_bit = a_bit ^ b_bit ^ expected;
-            result.push(res_bit);
+    /// Append this node's leaves to `out`, left to right.
+    fn flatten_into(&self, out: &mut Bits) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            match node {
+                Node::Bit(b) => out.push(*b),
+                Node::Compound(compound) => {
+                    let (left, right) = compound.children();
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
         }
+    }
 
-        result
+    /// Merkle-style content digest of the tree (see [`crate::digest`]).
+    ///
+    /// Equal trees, and only equal trees, share a fingerprint. Each
+    /// compound's digest is cached, so a subtree is hashed at most once.
+    pub fn fingerprint(&self) -> Digest {
+        // Post-order: children are hashed before the compound joining them.
+        let (zero, one) = (Digest::leaf(false), Digest::leaf(true));
+        let mut todo = vec![(self, false)];
+        let mut hashed: Vec<Digest> = Vec::new();
+        while let Some((node, children_hashed)) = todo.pop() {
+            match node {
+                Node::Bit(b) => hashed.push(if *b { one } else { zero }),
+                Node::Compound(compound) if compound.digest.get().is_some() => {
+                    hashed.push(*compound.digest.get().expect("cached digest"));
+                }
+                Node::Compound(compound) if children_hashed => {
+                    let right = hashed.pop().expect("right child hashed");
+                    let left = hashed.pop().expect("left child hashed");
+                    let digest = Digest::compound(&left, &right);
+                    let _ = compound.digest.set(digest);
+                    hashed.push(digest);
+                }
+                Node::Compound(compound) => {
+                    let (left, right) = compound.children();
+                    todo.push((node, true));
+                    todo.push((right, false));
+                    todo.push((left, false));
+                }
+            }
+        }
+        hashed.pop().expect("one root")
+    }
+
+    /// The subtree at `path` (`0` = left, `1` = right), if the tree reaches
+    /// that far.
+    pub fn subtree(&self, path: &BitSlice<u8, Msb0>) -> Option<&Node> {
+        let mut at = self;
+        for side in path.iter().by_vals() {
+            at = match at {
+                Node::Compound(compound) if side => compound.right(),
+                Node::Compound(compound) => compound.left(),
+                Node::Bit(_) => return None,
+            };
+        }
+        Some(at)
     }
 
+    /// The tree in pre-order, one token per node.
+    fn tokens(&self) -> Tokens<'_> {
+        Tokens { stack: vec![self] }
+    }
+
+    /// Number of leaves, cached on each compound.
     pub fn size(&self) -> usize {
         match self {
             Node::Bit(_) => 1,
+            Node::Compound(compound) => compound.leaves,
+        }
+    }
+
+    /// Whether both are the same compound, or equal leaves.
+    pub(crate) fn same(&self, other: &Node) -> bool {
+        match (self, other) {
+            (Node::Compound(a), Node::Compound(b)) => Arc::ptr_eq(a, b),
+            (Node::Bit(a), Node::Bit(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// One node of a pre-order walk. The variant order matches the codec's
+/// prefix code (`0 0` < `0 1` < `1`), so token streams sort like encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Token {
+    Bit(bool),
+    Compound,
+}
+
+/// Iterative pre-order walk over a tree.
+struct Tokens<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        match self.stack.pop()? {
+            Node::Bit(b) => Some(Token::Bit(*b)),
             Node::Compound(compound) => {
-                let (left, right) = compound.as_ref();
-                left.size() + right.size()
+                let (left, right) = compound.children();
+                self.stack.push(right);
+                self.stack.push(left);
+                Some(Token::Compound)
             }
         }
     }
 }
 
-impl Into<bool> for Node {
-    fn into(self) -> bool {
-        let entropy = &mut UniversalEntropy::new();
-        match self {
-            Node::Bit(_) => self.into(),
-            Node::Compound(compound) => {
-                let (left, right) = *compound;
-                let left_bit: bool = left.into();
-                let right_bit: bool = right.into();
-                left_bit ^ right_bit ^ entropy.bit(0)
+/// Structural equality: same shape, same leaves.
+///
+/// Every compound is interned, so equal trees are the same compound and
+/// this is a pointer compare.
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        self.same(other)
+    }
+}
+
+impl Eq for Node {}
+
+/// Hashes the [`fingerprint`](Node::fingerprint), cached after the first
+/// time.
+impl Hash for Node {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.fingerprint().hash(state);
+    }
+}
+
+/// Nodes are ordered like their [`codec`](crate::codec) encodings.
+impl Ord for Node {
+    fn cmp(&self, other: &Node) -> Ordering {
+        if self.same(other) {
+            return Ordering::Equal;
+        }
+        self.tokens().cmp(other.tokens())
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// [`Node::op`] of two trees, given their wider size and combined parity.
+fn op_bits(entropy: &mut (impl EntropySource + ?Sized), size: usize, parity: bool) -> Bits {
+    // e ^ e ^ e = e: one expected bit per position survives.
+    let result = entropy.range(0, size);
+    if parity {
+        !result
+    } else {
+        result
+    }
+}
+
+/// Append the fold of `leaves` at position `leaves.len()`.
+fn seal(entropy: &mut (impl EntropySource + ?Sized), leaves: &mut Bits) {
+    let bit = (leaves.count_ones() % 2 == 1) ^ entropy.bit(leaves.len());
+    leaves.push(bit);
+}
+
+/// Fold `leaves` down to at most [`MAX_LEAVES`], keeping their parity.
+fn bound_leaves(mut leaves: Bits) -> Bits {
+    while leaves.len() > MAX_LEAVES {
+        // XOR the top half onto the bottom half, so no leaf is lost
+        // without a trace and the size keeps moving the port.
+        let half = leaves.len().div_ceil(2);
+        for high in half..leaves.len() {
+            let low = high - half;
+            let folded = leaves[low] ^ leaves[high];
+            leaves.set(low, folded);
+        }
+        leaves.truncate(half);
+    }
+    leaves
+}
+
+/// Tear trees down iteratively, so dropping the last handle on a deep tree
+/// cannot overflow the stack.
+impl Drop for Compound {
+    fn drop(&mut self) {
+        fn take_children(children: &mut (Node, Node), stack: &mut Vec<Arc<Compound>>) {
+            for child in [&mut children.0, &mut children.1] {
+                if let Node::Compound(_) = child {
+                    let Node::Compound(shared) = std::mem::replace(child, Node::Bit(false)) else {
+                        unreachable!("checked above");
+                    };
+                    stack.push(shared);
+                }
             }
         }
+
+        let mut stack = Vec::new();
+        take_children(&mut self.children, &mut stack);
+        while let Some(shared) = stack.pop() {
+            // Still held elsewhere: someone else tears it down later.
+            if let Some(mut compound) = Arc::into_inner(shared) {
+                take_children(&mut compound.children, &mut stack);
+            }
+        }
+    }
+}
+
+/// Every live compound, by its two children, so [`Node::compound`] can
+/// hand out the existing one.
+///
+/// Children are told apart by identity: a leaf by its value, a compound by
+/// its address. A live compound holds its children, so their addresses
+/// cannot be reused while its entry can still be found.
+struct Interner {
+    built: HashMap<(usize, usize), Weak<Compound>>,
+    /// Entry count at which dropped compounds are next swept out.
+    sweep_at: usize,
+}
+
+/// Entries kept before the first sweep.
+const SWEEP_MIN: usize = 1 << 16;
+
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(|| {
+    Mutex::new(Interner {
+        built: HashMap::new(),
+        sweep_at: SWEEP_MIN,
+    })
+});
+
+impl Interner {
+    /// A key for `node`: its value for a leaf, its address for a compound.
+    fn key(node: &Node) -> usize {
+        match node {
+            Node::Bit(b) => *b as usize,
+            Node::Compound(compound) => Arc::as_ptr(compound) as usize,
+        }
+    }
+
+    /// Record `compound` under `key`, sweeping out dropped compounds once
+    /// the table has doubled since the last sweep.
+    fn insert(&mut self, key: (usize, usize), compound: &Arc<Compound>) {
+        self.built.insert(key, Arc::downgrade(compound));
+        if self.built.len() >= self.sweep_at {
+            self.built.retain(|_, compound| compound.strong_count() > 0);
+            self.sweep_at = (2 * self.built.len()).max(SWEEP_MIN);
+        }
+    }
+}
+
+/// Each compound XORs its children with `e(0)`: with `n - 1` compounds
+/// over `n` leaves, that is the leaf parity, XOR `e(0)` when `n` is even.
+impl From<Node> for bool {
+    fn from(node: Node) -> Self {
+        let entropy = &mut UniversalEntropy::new();
+        node.parity() ^ (node.size().is_multiple_of(2) && entropy.bit(0))
     }
 }
 
-/// Flatten leaf or compound node into bytes.
+/// Flatten leaf or compound node into bytes, padding the final byte with zeros.
 impl From<Node> for Vec<u8> {
     fn from(n: Node) -> Self {
-        match n {
-            Node::Bit(false) => ZERO_BLOCK.to_vec(),
-            Node::Bit(true) => ONE_BLOCK.to_vec(),
-            Node::Compound(pair) => {
-                let (l, r) = *pair;
-                let mut bits: Bits = BitVec::new();
-                bits.extend_from_bitslice(&Bits::from(l));
-                bits.extend_from_bitslice(&Bits::from(r));
-                bits.into()
-            }
-        }
+        Bits::from(&n).into_vec()
     }
 }
 
+/// Flatten node → Bits, one bit per leaf in left-to-right order.
 impl From<&Node> for Bits {
     fn from(n: &Node) -> Self {
-        match n {
-            Node::Bit(false) => BitVec::from_slice(ZERO_BLOCK),
-            Node::Bit(true) => BitVec::from_slice(ONE_BLOCK),
-            Node::Compound(value) => {
-                let (left, right) = value.as_ref();
-                let mut out = Bits::new();
-                let left_bits: Bits = Bits::from(left);
-                let right_bits: Bits = Bits::from(right);
-                out.extend_from_bitslice(&left_bits.to_owned());
-                out.extend_from_bitslice(&right_bits.to_owned());
-                out
-            }
-        }
+        let mut out = Bits::with_capacity(n.size());
+        n.flatten_into(&mut out);
+        out
     }
 }
 
@@ -166,43 +474,49 @@ impl From<SocketAddr> for Node {
     }
 }
 
-/// Build a Node from Bits by recursively splitting.
+/// Build a Node from Bits by splitting in half down to single bits.
+///
+/// Lossless: every input bit becomes exactly one `Node::Bit` leaf, so
+/// flattening the result yields the original bits.
 impl From<Bits> for Node {
     fn from(bv: Bits) -> Self {
-        if bv.is_empty() {
+        Node::from(bv.as_bitslice())
+    }
+}
+
+impl From<&BitSlice<u8, Msb0>> for Node {
+    fn from(bits: &BitSlice<u8, Msb0>) -> Self {
+        if bits.is_empty() {
             panic!("Node::from: empty bitvector.");
         }
 
-        const LEAF_THRESHOLD: usize = 64;
+        // Ranges still to build, and whether their halves are already built
+        // and waiting on `built` to be joined.
+        let mut todo = vec![(0, bits.len(), false)];
+        let mut built: Vec<Node> = Vec::new();
 
-        if bv.len() <= LEAF_THRESHOLD {
-            let ones = bv.iter().by_vals().filter(|b| *b).count();
-            let zeros = bv.len() - ones;
-            return Node::Bit(if ones >= zeros { true } else { false });
+        while let Some((start, end, halves_built)) = todo.pop() {
+            if halves_built {
+                let right = built.pop().expect("right half built");
+                let left = built.pop().expect("left half built");
+                built.push(Node::compound(left, right));
+            } else if end - start == 1 {
+                built.push(Node::Bit(bits[start]));
+            } else {
+                let mid = start + (end - start) / 2;
+                todo.push((start, end, true));
+                todo.push((mid, end, false));
+                todo.push((start, mid, false));
+            }
         }
 
-        let mid = bv.len() / 2;
-        let left = Node::from(bv[..mid].to_bitvec());
-        let right = Node::from(bv[mid..].to_bitvec());
-
-        let node = Node::Compound(Box::new((left, right)));
-        node
+        built.pop().expect("one root")
     }
 }
 
 /// Flatten node → Bits.
 impl From<Node> for Bits {
     fn from(n: Node) -> Self {
-        match n {
-            Node::Bit(false) => BitVec::from_slice(ZERO_BLOCK),
-            Node::Bit(true) => BitVec::from_slice(ONE_BLOCK),
-            Node::Compound(pair) => {
-                let (l, r) = *pair;
-                let mut out = Bits::new();
-                out.extend_from_bitslice(&Bits::from(l));
-                out.extend_from_bitslice(&Bits::from(r));
-                out
-            }
-        }
+        Bits::from(&n)
     }
 }
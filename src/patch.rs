@@ -0,0 +1,221 @@
+//! Structural deltas between Node trees.
+//!
+//! A [`NodePatch`] is an ordered list of subtree replacements, each addressed
+//! by its path from the root: one bit per step, `0` = left, `1` = right.
+//!
+//! Layout: `[version u8][u32 count]` then, per change,
+//! `[u32 path bits][path bits, zero padded to a byte][u32 len][len bytes]`,
+//! big-endian, where the replacement subtree is written with [`codec::encode`].
+
+use std::io;
+
+use crate::{
+    codec,
+    node::{Bits, Node},
+};
+
+/// Current patch encoding version, written as the first byte of every patch.
+pub const VERSION: u8 = 1;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Split `n` bytes off the front of `rest`.
+fn take<'a>(rest: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if rest.len() < n {
+        return Err(invalid("truncated patch"));
+    }
+    let (head, tail) = rest.split_at(n);
+    *rest = tail;
+    Ok(head)
+}
+
+fn take_u32(rest: &mut &[u8]) -> io::Result<usize> {
+    let bytes = take(rest, 4)?.try_into().expect("4 bytes");
+    Ok(u32::from_be_bytes(bytes) as usize)
+}
+
+/// Subtree replacements turning one Node into another.
+#[derive(Debug, Clone, Default)]
+pub struct NodePatch {
+    changes: Vec<(Bits, Node)>,
+}
+
+impl NodePatch {
+    /// An empty patch, leaving any Node unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a change replacing the subtree at `path` with `subtree`, applied
+    /// after every change already in the patch.
+    pub fn replace(&mut self, path: Bits, subtree: Node) {
+        self.changes.push((path, subtree));
+    }
+
+    /// The `(path, subtree)` replacements, in the order they apply.
+    pub fn changes(&self) -> &[(Bits, Node)] {
+        &self.changes
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The patch turning `old` into `new`.
+    ///
+    /// Walks both trees together and replaces the subtrees of `new` that
+    /// differ at the same path. Where patching the children piecemeal would
+    /// cost more than replacing their parent outright, the parent is
+    /// replaced instead.
+    pub fn between(old: &Node, new: &Node) -> Self {
+        let mut patch = Self::new();
+        diff_into(old, new, &mut Bits::new(), &mut patch.changes);
+        patch
+    }
+
+    /// Apply every change to `node` in order.
+    ///
+    /// Only the compounds along each path are rebuilt; everything else is
+    /// shared with `node`. Fails with `InvalidData` when a path runs past a
+    /// leaf.
+    pub fn apply_to(&self, mut node: Node) -> io::Result<Node> {
+        for (path, subtree) in &self.changes {
+            // Walk down, remembering the compounds passed on the way.
+            let mut passed = Vec::with_capacity(path.len());
+            let mut at = &node;
+            for side in path.iter().by_vals() {
+                let Node::Compound(compound) = at else {
+                    return Err(invalid("patch path runs past a leaf"));
+                };
+                passed.push((compound, side));
+                at = if side {
+                    compound.right()
+                } else {
+                    compound.left()
+                };
+            }
+
+            // Rebuild the spine bottom-up around the new subtree.
+            let mut rebuilt = subtree.clone();
+            for (compound, side) in passed.into_iter().rev() {
+                rebuilt = if side {
+                    Node::compound(compound.left().clone(), rebuilt)
+                } else {
+                    Node::compound(rebuilt, compound.right().clone())
+                };
+            }
+            node = rebuilt;
+        }
+        Ok(node)
+    }
+
+    /// Serialize into the versioned patch encoding.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![VERSION];
+        out.extend_from_slice(&(self.changes.len() as u32).to_be_bytes());
+        for (path, node) in &self.changes {
+            let node = codec::encode(node);
+            out.extend_from_slice(&(path.len() as u32).to_be_bytes());
+            out.extend_from_slice(path.as_raw_slice());
+            out.extend_from_slice(&(node.len() as u32).to_be_bytes());
+            out.extend_from_slice(&node);
+        }
+        out
+    }
+
+    /// Parse bytes produced by [`encode`](NodePatch::encode).
+    ///
+    /// Fails with `InvalidData` on an unknown version, a truncated patch,
+    /// non-zero path padding or trailing bytes.
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let (&version, mut rest) = bytes.split_first().ok_or_else(|| invalid("empty patch"))?;
+        if version != VERSION {
+            return Err(invalid(format!("unsupported patch version {}", version)));
+        }
+
+        let count = take_u32(&mut rest)?;
+        let mut patch = Self::new();
+        for _ in 0..count {
+            let bits = take_u32(&mut rest)?;
+            let mut path = Bits::from_slice(take(&mut rest, bits.div_ceil(8))?);
+            if path[bits..].any() {
+                return Err(invalid("non-zero padding in patch path"));
+            }
+            path.truncate(bits);
+            let len = take_u32(&mut rest)?;
+            patch.replace(path, codec::decode(take(&mut rest, len)?)?);
+        }
+        if !rest.is_empty() {
+            return Err(invalid("trailing data after patch"));
+        }
+        Ok(patch)
+    }
+}
+
+/// Rough encoded size in bits of one change putting `leaves` leaves at
+/// `path`.
+fn change_cost(path: &Bits, leaves: usize) -> usize {
+    64 + path.len() + 8 + 3 * leaves
+}
+
+/// One step of the walk in [`diff_into`].
+enum Step<'a> {
+    /// Compare `old` and `new` at the current path.
+    Visit(&'a Node, &'a Node),
+    /// Descend into a child.
+    Push(bool),
+    /// Come back up from a child.
+    Pop,
+    /// Both children of `new` are diffed from change `start` on: replace
+    /// `new` whole if that is cheaper.
+    Join { start: usize, new: &'a Node },
+}
+
+/// Collect the subtrees of `new` that differ from `old` at the same path.
+///
+/// Iterative, so diffing deep trees cannot overflow the stack.
+fn diff_into(old: &Node, new: &Node, path: &mut Bits, out: &mut Vec<(Bits, Node)>) {
+    let mut steps = vec![Step::Visit(old, new)];
+    while let Some(step) = steps.pop() {
+        match step {
+            // Shared subtrees are unchanged without a look inside.
+            Step::Visit(old, new) if old.same(new) => {}
+            Step::Visit(Node::Compound(old), new @ Node::Compound(compound)) => {
+                steps.push(Step::Join {
+                    start: out.len(),
+                    new,
+                });
+                let sides = [
+                    (true, (old.right(), compound.right())),
+                    (false, (old.left(), compound.left())),
+                ];
+                for (side, (old, new)) in sides {
+                    steps.push(Step::Pop);
+                    steps.push(Step::Visit(old, new));
+                    steps.push(Step::Push(side));
+                }
+            }
+            Step::Visit(_, new) => out.push((path.clone(), new.clone())),
+            Step::Push(side) => path.push(side),
+            Step::Pop => {
+                path.pop();
+            }
+            Step::Join { start, new } => {
+                let cost: usize = out[start..]
+                    .iter()
+                    .map(|(p, n)| change_cost(p, n.size()))
+                    .sum();
+                if cost > change_cost(path, new.size()) {
+                    out.truncate(start);
+                    out.push((path.clone(), new.clone()));
+                }
+            }
+        }
+    }
+}
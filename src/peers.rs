@@ -0,0 +1,180 @@
+//! Liveness bookkeeping for the peers a node has heard from.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{digest::Digest, node::Node};
+
+/// How often a node pings its peers by default.
+pub const HEARTBEAT: Duration = Duration::from_secs(5);
+
+/// How long a peer may stay silent before it is evicted by default.
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// What we know about a single peer.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    /// Round trip of the most recently answered ping.
+    pub rtt: Option<Duration>,
+    /// Messages received from this peer.
+    pub received: u64,
+    /// Messages sent to this peer.
+    pub sent: u64,
+    /// Latest verified copy of the peer's state, if any.
+    pub state: Option<Node>,
+    /// Outstanding ping: nonce and when it was sent.
+    ping: Option<(u64, Instant)>,
+}
+
+impl Peer {
+    fn new(addr: SocketAddr, now: Instant) -> Self {
+        Peer {
+            addr,
+            first_seen: now,
+            last_seen: now,
+            rtt: None,
+            received: 0,
+            sent: 0,
+            state: None,
+            ping: None,
+        }
+    }
+
+    /// Root digest of the copy of the peer's state we hold.
+    pub fn state_digest(&self) -> Option<Digest> {
+        self.state.as_ref().map(Node::fingerprint)
+    }
+
+    /// Time since this peer was last heard from.
+    pub fn silence(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_seen)
+    }
+}
+
+/// Known peers, keyed by address, with eviction after a silence window.
+#[derive(Debug, Clone)]
+pub struct PeerTable {
+    peers: HashMap<SocketAddr, Peer>,
+    timeout: Duration,
+}
+
+impl Default for PeerTable {
+    fn default() -> Self {
+        Self::new(PEER_TIMEOUT)
+    }
+}
+
+impl PeerTable {
+    /// Create an empty table evicting peers silent for longer than `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        PeerTable {
+            peers: HashMap::new(),
+            timeout,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Record a message received from `addr`; returns true for a new peer.
+    pub fn seen(&mut self, addr: SocketAddr, now: Instant) -> bool {
+        let mut new = false;
+        let peer = self.peers.entry(addr).or_insert_with(|| {
+            new = true;
+            Peer::new(addr, now)
+        });
+        peer.last_seen = now;
+        peer.received += 1;
+        new
+    }
+
+    /// Record a message sent to `addr`, if it is a known peer.
+    pub fn sent(&mut self, addr: SocketAddr) {
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.sent += 1;
+        }
+    }
+
+    /// Remember an outstanding ping so its pong can be timed.
+    pub fn ping(&mut self, addr: SocketAddr, nonce: u64, now: Instant) {
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.ping = Some((nonce, now));
+        }
+    }
+
+    /// Match a pong against the outstanding ping, returning the round trip.
+    pub fn pong(&mut self, addr: SocketAddr, nonce: u64, now: Instant) -> Option<Duration> {
+        let peer = self.peers.get_mut(&addr)?;
+        match peer.ping {
+            Some((expected, sent_at)) if expected == nonce => {
+                let rtt = now.saturating_duration_since(sent_at);
+                peer.ping = None;
+                peer.rtt = Some(rtt);
+                Some(rtt)
+            }
+            _ => None,
+        }
+    }
+
+    /// Record `state` as the peer's current state, if it is a known peer.
+    pub fn set_state(&mut self, addr: SocketAddr, state: Node) {
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.state = Some(state);
+        }
+    }
+
+    pub fn remove(&mut self, addr: &SocketAddr) -> Option<Peer> {
+        self.peers.remove(addr)
+    }
+
+    /// Evict every peer silent for longer than the timeout.
+    pub fn expire(&mut self, now: Instant) -> Vec<Peer> {
+        let timeout = self.timeout;
+        let stale: Vec<SocketAddr> = self
+            .peers
+            .values()
+            .filter(|peer| peer.silence(now) > timeout)
+            .map(|peer| peer.addr)
+            .collect();
+
+        stale
+            .iter()
+            .filter_map(|addr| self.peers.remove(addr))
+            .collect()
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&Peer> {
+        self.peers.get(addr)
+    }
+
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.peers.contains_key(addr)
+    }
+
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.peers.keys().copied().collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Peer> {
+        self.peers.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
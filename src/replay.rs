@@ -0,0 +1,133 @@
+//! Deterministic replay of a journaled frame log.
+//!
+//! Every input a node folded in is journaled next to the states it led to,
+//! so the whole history can be re-run with a fresh [`UniversalEntropy`] and
+//! checked bit-for-bit against what was recorded.
+
+use std::io;
+
+use crate::{
+    entropy::UniversalEntropy,
+    node::Node,
+    store::{Record, Store},
+};
+
+/// A recorded state that did not match the replayed one.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// Index of the offending record in the log.
+    pub record: usize,
+    pub expected: Node,
+    pub replayed: Node,
+}
+
+/// Outcome of replaying a log.
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    pub records: usize,
+    pub snapshots: usize,
+    pub inputs: usize,
+    /// Derived states that matched the replay.
+    pub verified: usize,
+    pub divergences: Vec<Divergence>,
+    /// Records that could not be read.
+    pub unreadable: usize,
+    /// The replayed node after the last record.
+    pub node: Option<Node>,
+}
+
+impl Replay {
+    pub fn is_consistent(&self) -> bool {
+        self.divergences.is_empty() && self.unreadable == 0
+    }
+}
+
+/// Re-run every journaled input through `Node::reflect` and compare each
+/// recorded state with the replayed one.
+///
+/// Snapshots reset the replay to the recorded node, and delta frames are
+/// rebuilt against the recorded state before them. After a divergence the
+/// replay continues from the recorded state so later frames are still checked.
+pub fn replay(store: &mut Store) -> io::Result<Replay> {
+    let mut entropy = UniversalEntropy::new();
+    let mut report = Replay::default();
+    let mut current: Option<Node> = None;
+    // The last recorded state, the base of the next delta frame.
+    let mut recorded: Option<Node> = None;
+
+    for (index, record) in store.records()?.enumerate() {
+        report.records += 1;
+
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("[REPLAY] Record {} unreadable: {}", index, e);
+                report.unreadable += 1;
+                recorded = None;
+                continue;
+            }
+        };
+
+        let expected = match record {
+            Record::Snapshot(node) => {
+                report.snapshots += 1;
+                recorded = Some(node.clone());
+                current = Some(node);
+                continue;
+            }
+            Record::Input(source, input) => {
+                report.inputs += 1;
+                let node = current.as_mut().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("record {}: input from {} before any state", index, source),
+                    )
+                })?;
+                *node = node.reflect(&mut entropy, &input);
+                continue;
+            }
+            Record::State(node) => node,
+            Record::Delta(patch) => {
+                let rebuilt = match recorded.take() {
+                    Some(base) => patch.apply_to(base),
+                    None => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "delta without a recorded state before it",
+                    )),
+                };
+                match rebuilt {
+                    Ok(node) => node,
+                    Err(e) => {
+                        eprintln!("[REPLAY] Record {} cannot be rebuilt: {}", index, e);
+                        report.unreadable += 1;
+                        continue;
+                    }
+                }
+            }
+        };
+        recorded = Some(expected.clone());
+
+        match current.take() {
+            Some(replayed) if replayed == expected => {
+                report.verified += 1;
+                current = Some(replayed);
+            }
+            Some(replayed) => {
+                eprintln!("[REPLAY] Record {} diverges from the replayed state", index);
+                report.divergences.push(Divergence {
+                    record: index,
+                    expected: expected.clone(),
+                    replayed,
+                });
+                current = Some(expected);
+            }
+            None => {
+                // A log without a leading snapshot: trust the first state.
+                current = Some(expected);
+            }
+        }
+    }
+
+    report.node = current;
+    Ok(report)
+}
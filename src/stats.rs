@@ -0,0 +1,307 @@
+//! Statistical quality tests for entropy streams.
+//!
+//! The tests follow NIST SP 800-22: each computes a statistic over the
+//! bits and the p-value of seeing it from truly random bits. A p-value
+//! below [`ALPHA`] fails the test.
+//!
+//! | test            | catches                                        |
+//! |-----------------|------------------------------------------------|
+//! | monobit         | more ones than zeros, or the reverse           |
+//! | block frequency | imbalance within blocks                        |
+//! | runs            | too many or too few changes between bits       |
+//! | serial          | some short patterns more frequent than others  |
+//! | autocorrelation | bits agreeing with the bits `lag` later        |
+
+use std::fmt;
+
+use bitvec::prelude::*;
+
+use crate::entropy::EntropySource;
+
+/// Significance level: a p-value below this fails.
+pub const ALPHA: f64 = 0.01;
+
+/// Block length of the default block frequency test.
+pub const BLOCK: usize = 128;
+
+/// Lags of the default autocorrelation tests.
+pub const LAGS: [usize; 4] = [1, 2, 8, 32];
+
+/// The result of one test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub name: String,
+    pub statistic: f64,
+    pub p_value: f64,
+}
+
+impl Outcome {
+    fn new(name: impl Into<String>, statistic: f64, p_value: f64) -> Self {
+        Outcome {
+            name: name.into(),
+            statistic,
+            p_value,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.p_value >= ALPHA
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<26} statistic {:>12.4}  p-value {:.6}  {}",
+            self.name,
+            self.statistic,
+            self.p_value,
+            if self.passed() { "PASS" } else { "FAIL" }
+        )
+    }
+}
+
+/// Every test run over one stretch of bits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// How many bits were tested.
+    pub bits: usize,
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    /// Run the whole suite with its default parameters over `bits`.
+    pub fn of(bits: &BitSlice<u8, Msb0>) -> Self {
+        let n = bits.len();
+        let mut outcomes = vec![monobit(bits), block_frequency(bits, BLOCK), runs(bits)];
+
+        // NIST: keep patterns shorter than log2(n) - 2.
+        let m = (n.max(1).ilog2() as usize).saturating_sub(3).clamp(2, 16);
+        let (first, second) = serial(bits, m);
+        outcomes.push(first);
+        outcomes.push(second);
+
+        outcomes.extend(LAGS.iter().map(|&lag| autocorrelation(bits, lag)));
+        Report { bits: n, outcomes }
+    }
+
+    /// Run the suite over the first `n` bits of `source`.
+    pub fn of_source(source: &mut (impl EntropySource + ?Sized), n: usize) -> Self {
+        Report::of(&source.range(0, n))
+    }
+
+    /// Whether every test passed.
+    pub fn passed(&self) -> bool {
+        self.outcomes.iter().all(Outcome::passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Outcome> {
+        self.outcomes.iter().filter(|outcome| !outcome.passed())
+    }
+}
+
+/// Frequency (monobit) test: the balance of ones and zeros.
+pub fn monobit(bits: &BitSlice<u8, Msb0>) -> Outcome {
+    let n = bits.len() as f64;
+    let sum = 2.0 * bits.count_ones() as f64 - n;
+    let s_obs = sum.abs() / n.sqrt();
+    Outcome::new("monobit", s_obs, erfc(s_obs / 2f64.sqrt()))
+}
+
+/// Frequency within blocks of `block` bits; the remainder is ignored.
+pub fn block_frequency(bits: &BitSlice<u8, Msb0>, block: usize) -> Outcome {
+    let blocks = bits.len() / block;
+    let chi_squared: f64 = bits
+        .chunks_exact(block)
+        .map(|chunk| {
+            let pi = chunk.count_ones() as f64 / block as f64;
+            (pi - 0.5).powi(2)
+        })
+        .sum::<f64>()
+        * 4.0
+        * block as f64;
+    Outcome::new(
+        format!("block frequency (M={})", block),
+        chi_squared,
+        igamc(blocks as f64 / 2.0, chi_squared / 2.0),
+    )
+}
+
+/// Runs test: the number of uninterrupted runs of equal bits.
+///
+/// Only meaningful when the monobit balance is reasonable; otherwise the
+/// p-value is 0.
+pub fn runs(bits: &BitSlice<u8, Msb0>) -> Outcome {
+    let n = bits.len() as f64;
+    let pi = bits.count_ones() as f64 / n;
+    if (pi - 0.5).abs() >= 2.0 / n.sqrt() {
+        return Outcome::new("runs", f64::NAN, 0.0);
+    }
+
+    let changes = bits.windows(2).filter(|pair| pair[0] != pair[1]).count();
+    let v_obs = (changes + 1) as f64;
+    let spread = 2.0 * pi * (1.0 - pi);
+    let p_value = erfc((v_obs - n * spread).abs() / (2.0 * (2.0 * n).sqrt() * pi * (1.0 - pi)));
+    Outcome::new("runs", v_obs, p_value)
+}
+
+/// Serial test: the frequencies of all overlapping `m` bit patterns.
+///
+/// Returns the two NIST p-values, from the first and second differences
+/// of the ψ² statistics for `m`, `m - 1` and `m - 2` bit patterns.
+pub fn serial(bits: &BitSlice<u8, Msb0>, m: usize) -> (Outcome, Outcome) {
+    assert!(
+        (2..=24).contains(&m),
+        "serial: pattern length {} out of range",
+        m
+    );
+
+    let psi = [
+        psi_squared(bits, m),
+        psi_squared(bits, m - 1),
+        psi_squared(bits, m - 2),
+    ];
+    let first = psi[0] - psi[1];
+    let second = psi[0] - 2.0 * psi[1] + psi[2];
+    (
+        Outcome::new(
+            format!("serial (m={}, ∇ψ²)", m),
+            first,
+            igamc(2f64.powi(m as i32 - 2), first / 2.0),
+        ),
+        Outcome::new(
+            format!("serial (m={}, ∇²ψ²)", m),
+            second,
+            igamc(2f64.powi(m as i32 - 3), second / 2.0),
+        ),
+    )
+}
+
+/// ψ² of the overlapping `m` bit patterns, the sequence wrapping around.
+fn psi_squared(bits: &BitSlice<u8, Msb0>, m: usize) -> f64 {
+    let n = bits.len();
+    if m == 0 || n == 0 {
+        return 0.0;
+    }
+
+    let mask = (1usize << m) - 1;
+    let mut counts = vec![0u64; 1 << m];
+    let mut pattern = 0;
+    // Prime with the first m - 1 bits; the last windows wrap to the start.
+    for i in 0..m - 1 {
+        pattern = (pattern << 1) | bits[i % n] as usize;
+    }
+    for i in m - 1..n + m - 1 {
+        pattern = ((pattern << 1) | bits[i % n] as usize) & mask;
+        counts[pattern] += 1;
+    }
+
+    let sum: f64 = counts.iter().map(|&c| (c as f64).powi(2)).sum();
+    sum * (1usize << m) as f64 / n as f64 - n as f64
+}
+
+/// Autocorrelation test: how often each bit differs from the bit `lag`
+/// places later.
+///
+/// Needs more than `lag` bits; otherwise the p-value is 0.
+pub fn autocorrelation(bits: &BitSlice<u8, Msb0>, lag: usize) -> Outcome {
+    let name = format!("autocorrelation (d={})", lag);
+    if bits.len() <= lag {
+        return Outcome::new(name, f64::NAN, 0.0);
+    }
+
+    let n = bits.len() - lag;
+    let differ = (bits[lag..].to_bitvec() ^ &bits[..n]).count_ones() as f64;
+    let z = 2.0 * (differ - n as f64 / 2.0) / (n as f64).sqrt();
+    Outcome::new(name, z, erfc(z.abs() / 2f64.sqrt()))
+}
+
+/// Complementary error function, to about 1e-7 relative accuracy
+/// (Chebyshev fit, Numerical Recipes `erfcc`).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let value = t * poly.exp();
+    if x >= 0.0 {
+        value
+    } else {
+        2.0 - value
+    }
+}
+
+/// ln Γ(x) for x > 0 (Lanczos approximation).
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.120_865_097_386_617_9e-2,
+        -0.539_523_938_495_3e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000_000_000_190_015;
+    for (i, c) in COEFFICIENTS.iter().enumerate() {
+        series += c / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+/// Regularized upper incomplete gamma function Q(a, x): the series for
+/// small `x`, the continued fraction (modified Lentz) otherwise.
+fn igamc(a: f64, x: f64) -> f64 {
+    const EPSILON: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let scale = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        let (mut term, mut sum, mut ap) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..10_000 {
+            ap += 1.0;
+            term *= x / ap;
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        return (1.0 - sum * scale).max(0.0);
+    }
+
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / TINY;
+    let mut d = 1.0 / b;
+    let mut h = d;
+    for i in 1..10_000 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = b + an / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+    scale * h
+}
@@ -1,95 +1,807 @@
-use std::fs::{File, OpenOptions};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
-use bitvec::prelude::*;
+use crate::codec;
+use crate::node::Node;
+use crate::patch::NodePatch;
 
-use crate::node::Node; // adjust to your actual module path
+const SNAPSHOT: u8 = 0x00;
+const STATE: u8 = 0x01;
+const INPUT: u8 = 0x02;
+const DELTA: u8 = 0x03;
 
-/// Simple append-only log of Node frames.
-/// File layout: [u32 len][len bytes of Node][u32 len][len bytes]...
+const SOURCE_ADDR_IN_USE: u8 = 0x00;
+const SOURCE_BIND_ERROR: u8 = 0x01;
+const SOURCE_STDIN: u8 = 0x02;
+const SOURCE_PEER: u8 = 0x03;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Where an input folded into the node came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The port derived from the node was already taken.
+    AddrInUse,
+    /// Binding failed for any other reason.
+    BindError,
+    /// A line typed on stdin (or handed to [`Mesh::input`](crate::Mesh::input)).
+    Stdin,
+    /// A message from a peer.
+    Peer(SocketAddr),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::AddrInUse => write!(f, "bind failure (address in use)"),
+            Source::BindError => write!(f, "bind failure"),
+            Source::Stdin => write!(f, "stdin"),
+            Source::Peer(addr) => write!(f, "peer {}", addr),
+        }
+    }
+}
+
+/// A single entry of the log.
+#[derive(Debug, Clone)]
+pub enum Record {
+    /// A node state that starts a new history (a fresh start or resume).
+    Snapshot(Node),
+    /// A node state derived from the previous state and the inputs since.
+    State(Node),
+    /// An input folded into the node with `Node::reflect`.
+    Input(Source, Node),
+    /// A node state given as the patch from the previous state.
+    Delta(NodePatch),
+}
+
+impl Record {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Record::Snapshot(node) => {
+                out.push(SNAPSHOT);
+                out.extend_from_slice(&codec::encode(node));
+            }
+            Record::State(node) => {
+                out.push(STATE);
+                out.extend_from_slice(&codec::encode(node));
+            }
+            Record::Input(source, node) => {
+                out.push(INPUT);
+                match source {
+                    Source::AddrInUse => out.push(SOURCE_ADDR_IN_USE),
+                    Source::BindError => out.push(SOURCE_BIND_ERROR),
+                    Source::Stdin => out.push(SOURCE_STDIN),
+                    Source::Peer(addr) => {
+                        let addr = addr.to_string();
+                        out.push(SOURCE_PEER);
+                        out.push(addr.len() as u8);
+                        out.extend_from_slice(addr.as_bytes());
+                    }
+                }
+                out.extend_from_slice(&codec::encode(node));
+            }
+            Record::Delta(patch) => {
+                out.push(DELTA);
+                out.extend_from_slice(&patch.encode());
+            }
+        }
+        out
+    }
+
+    fn decode(data: &[u8]) -> io::Result<Record> {
+        let (&kind, body) = data.split_first().ok_or_else(|| invalid("empty frame"))?;
+        match kind {
+            SNAPSHOT => Ok(Record::Snapshot(codec::decode(body)?)),
+            STATE => Ok(Record::State(codec::decode(body)?)),
+            INPUT => {
+                let (&tag, rest) = body
+                    .split_first()
+                    .ok_or_else(|| invalid("input frame without source"))?;
+                let (source, rest) = match tag {
+                    SOURCE_ADDR_IN_USE => (Source::AddrInUse, rest),
+                    SOURCE_BIND_ERROR => (Source::BindError, rest),
+                    SOURCE_STDIN => (Source::Stdin, rest),
+                    SOURCE_PEER => {
+                        let (&len, rest) = rest
+                            .split_first()
+                            .ok_or_else(|| invalid("peer source without address"))?;
+                        let len = len as usize;
+                        if rest.len() < len {
+                            return Err(invalid("truncated peer address"));
+                        }
+                        let addr = std::str::from_utf8(&rest[..len])
+                            .ok()
+                            .and_then(|s| s.parse().ok())
+                            .ok_or_else(|| invalid("bad peer address"))?;
+                        (Source::Peer(addr), &rest[len..])
+                    }
+                    other => return Err(invalid(format!("unknown input source {}", other))),
+                };
+                Ok(Record::Input(source, codec::decode(rest)?))
+            }
+            DELTA => Ok(Record::Delta(NodePatch::decode(body)?)),
+            other => Err(invalid(format!("unknown frame kind {}", other))),
+        }
+    }
+}
+
+/// Magic bytes opening every log file.
+pub const MAGIC: &[u8; 4] = b"hey,";
+
+/// Current log format version, written right after [`MAGIC`].
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Size of the file header: magic + version.
+pub const HEADER_LEN: u64 = 5;
+
+/// Size of the per-frame header: length + checksum.
+const FRAME_HEADER_LEN: u64 = 8;
+
+/// Largest payload a single frame may carry; anything bigger is corruption.
+pub const MAX_FRAME: u32 = 16 * 1024 * 1024;
+
+/// Delta frames written in a row before a full state frame is forced,
+/// bounding how far back a reader has to go to rebuild a state.
+pub const KEYFRAME_INTERVAL: usize = 64;
+
+/// Size of one sidecar index entry: a `u64` byte offset.
+const INDEX_ENTRY_LEN: u64 = 8;
+
+/// The sidecar index path for a log: `<log>.idx`.
+pub fn index_path(log: &Path) -> PathBuf {
+    let mut path = OsString::from(log.as_os_str());
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+fn open_rw(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+/// How much history [`Store::compact`] keeps.
+///
+/// Both limits apply when set; `None` leaves that dimension unbounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Keep at most this many of the newest frames.
+    pub frames: Option<usize>,
+    /// Keep at most this many bytes of the newest frames.
+    pub bytes: Option<u64>,
+}
+
+impl Retention {
+    pub fn is_unbounded(&self) -> bool {
+        self.frames.is_none() && self.bytes.is_none()
+    }
+}
+
+/// Sizes before and after a compaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compaction {
+    pub frames_before: usize,
+    pub frames_after: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Wrap a record payload as `[u32 len][u32 crc32][payload]`.
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN as usize + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&crc32fast::hash(payload).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// What was found at a frame offset.
+enum Scan {
+    /// A complete frame whose checksum matches.
+    Frame { payload: Vec<u8>, next: u64 },
+    /// A complete frame whose checksum does not match.
+    Corrupt { next: u64 },
+    /// Clean end of the log.
+    End,
+    /// A frame running past the end of the file.
+    Torn,
+}
+
+/// Read the frame starting at `pos` in a log of `end` bytes.
+///
+/// A length over [`MAX_FRAME`] is corruption rather than a torn append,
+/// unless nothing but the header is left, and fails with `InvalidData`.
+fn scan(file: &mut File, pos: u64, end: u64) -> io::Result<Scan> {
+    if pos >= end {
+        return Ok(Scan::End);
+    }
+    if end - pos < FRAME_HEADER_LEN {
+        return Ok(Scan::Torn);
+    }
+
+    let mut header = [0u8; FRAME_HEADER_LEN as usize];
+    file.seek(SeekFrom::Start(pos))?;
+    file.read_exact(&mut header)?;
+
+    let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let crc = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    let next = pos + FRAME_HEADER_LEN + len as u64;
+    if len > MAX_FRAME && end - pos > FRAME_HEADER_LEN {
+        return Err(invalid(format!(
+            "frame at offset {} claims {} bytes, over the {} byte limit",
+            pos, len, MAX_FRAME
+        )));
+    }
+    if len > MAX_FRAME || next > end {
+        return Ok(Scan::Torn);
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    file.read_exact(&mut payload)?;
+
+    if crc32fast::hash(&payload) != crc {
+        return Ok(Scan::Corrupt { next });
+    }
+    Ok(Scan::Frame { payload, next })
+}
+
+/// Simple append-only log of Node frames and the inputs between them.
+///
+/// File layout: `[MAGIC][version u8]` then frames of
+/// `[u32 len][u32 crc32 of payload][len bytes of Record]`, big-endian,
+/// where each Node inside a Record is written with [`codec::encode`].
+///
+/// Node states are written as [`Record::Delta`]s against the previous state
+/// whenever that is smaller, with a full state at least every
+/// [`KEYFRAME_INTERVAL`] frames; [`Store::state_at`] and [`Store::iter`]
+/// rebuild the full states.
+///
+/// A sidecar index (`<log>.idx`, one big-endian `u64` byte offset per
+/// frame) gives O(1) access to any frame by number. It is only a cache:
+/// anything missing or inconsistent is rebuilt from the log on open.
 pub struct Store {
+    path: PathBuf,
     file: File,
+    index_file: File,
+    /// Byte offset of every frame, in order.
+    index: Vec<u64>,
+    /// Length of the log in bytes.
+    end: u64,
+    /// The last state written through this handle, the base for the next delta.
+    head: Option<Node>,
+    /// Deltas written since the last full state.
+    since_keyframe: usize,
 }
 
 impl Store {
     /// Open (or create) the log file.
+    ///
+    /// An existing log must carry the expected header. A torn frame at the
+    /// tail (an interrupted append) is truncated away before returning; a
+    /// frame with an impossible length fails with `InvalidData`, leaving
+    /// the log as it is.
     pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)?;
+        let path = path.as_ref();
+        let file = open_rw(path)?;
+        let index_file = open_rw(&index_path(path))?;
 
-        Ok(Self { file })
+        let mut store = Self {
+            path: path.to_path_buf(),
+            file,
+            index_file,
+            index: Vec::new(),
+            end: 0,
+            head: None,
+            since_keyframe: 0,
+        };
+        store.check_header()?;
+        store.end = store.file.metadata()?.len();
+        store.load_index()?;
+        store.recover()?;
+        Ok(store)
     }
 
-    /// Append a single Node as a frame.
-    pub fn append_frame(&mut self, node: &Node) -> io::Result<()> {
-        // Convert Node -> Vec<u8> (you already have impl From<Node> for Vec<u8>)
-        let bytes: Vec<u8> = node.clone().into();
-        let len = bytes.len() as u32;
+    /// Load the sidecar index, keeping only entries consistent with the log.
+    ///
+    /// The last kept entry must point at an intact frame; if it does not,
+    /// or reading there fails, the index is discarded and
+    /// [`recover`](Store::recover) rebuilds it.
+    fn load_index(&mut self) -> io::Result<()> {
+        let mut raw = Vec::new();
+        self.index_file.seek(SeekFrom::Start(0))?;
+        self.index_file.read_to_end(&mut raw)?;
 
-        // Seek to end to keep it append-only.
-        self.file.seek(SeekFrom::End(0))?;
+        let mut index: Vec<u64> = Vec::with_capacity(raw.len() / INDEX_ENTRY_LEN as usize);
+        for entry in raw.chunks_exact(INDEX_ENTRY_LEN as usize) {
+            let offset = u64::from_be_bytes(entry.try_into().expect("8 byte chunk"));
+            let expected_first = index.is_empty() && offset != HEADER_LEN;
+            let out_of_order = index.last().is_some_and(|&prev| offset <= prev);
+            if expected_first || out_of_order || offset >= self.end {
+                break;
+            }
+            index.push(offset);
+        }
+
+        if let Some(&last) = index.last() {
+            if !matches!(scan(&mut self.file, last, self.end), Ok(Scan::Frame { .. })) {
+                eprintln!("[STORE] Index does not match the log; rebuilding it.");
+                index.clear();
+            }
+        }
+
+        self.index = index;
+        Ok(())
+    }
+
+    /// Rewrite the sidecar index from entry `from` onwards.
+    fn write_index(&mut self, from: usize) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity((self.index.len() - from) * INDEX_ENTRY_LEN as usize);
+        for offset in &self.index[from..] {
+            bytes.extend_from_slice(&offset.to_be_bytes());
+        }
+        self.index_file.set_len(from as u64 * INDEX_ENTRY_LEN)?;
+        self.index_file.seek(SeekFrom::End(0))?;
+        self.index_file.write_all(&bytes)?;
+        self.index_file.flush()
+    }
+
+    /// Write the header into an empty log, or verify an existing one.
+    fn check_header(&mut self) -> io::Result<()> {
+        let mut expected = MAGIC.to_vec();
+        expected.push(FORMAT_VERSION);
+
+        let len = self.file.metadata()?.len();
+        let mut found = vec![0u8; len.min(HEADER_LEN) as usize];
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_exact(&mut found)?;
+
+        if found.len() < expected.len() && expected.starts_with(&found) {
+            // Empty, or the header itself was torn: (re)write it.
+            self.file.set_len(0)?;
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(&expected)?;
+            return self.file.sync_all();
+        }
+
+        if found.len() < expected.len() || found[..4] != MAGIC[..] {
+            return Err(invalid("not a hey, log (bad magic)"));
+        }
+        if found[4] != FORMAT_VERSION {
+            return Err(invalid(format!("unsupported log version {}", found[4])));
+        }
+        Ok(())
+    }
+
+    /// Walk the frames after the last indexed one, indexing them and
+    /// truncating a torn tail left by an interrupted append.
+    ///
+    /// A corrupt frame in the middle of the log is kept (reading it reports
+    /// the error); a corrupt *final* frame is treated as torn.
+    fn recover(&mut self) -> io::Result<()> {
+        let indexed = self.index.len();
+        let end = self.end;
+        let mut pos = match self.index.last() {
+            Some(&last) => match scan(&mut self.file, last, end)? {
+                Scan::Frame { next, .. } => next,
+                _ => unreachable!("load_index keeps only intact last frames"),
+            },
+            None => HEADER_LEN,
+        };
+
+        loop {
+            match scan(&mut self.file, pos, end)? {
+                Scan::Frame { next, .. } => {
+                    self.index.push(pos);
+                    pos = next;
+                }
+                Scan::Corrupt { next } if next < end => {
+                    eprintln!("[STORE] Checksum mismatch in frame at offset {}", pos);
+                    self.index.push(pos);
+                    pos = next;
+                }
+                Scan::End => break,
+                Scan::Corrupt { .. } | Scan::Torn => {
+                    eprintln!(
+                        "[STORE] Truncating torn frame at offset {} ({} bytes dropped)",
+                        pos,
+                        end - pos
+                    );
+                    self.file.set_len(pos)?;
+                    self.file.sync_all()?;
+                    self.end = pos;
+                    break;
+                }
+            }
+        }
+
+        // Entries before `indexed` were read from the sidecar; rewrite the rest.
+        let on_disk = self.index_file.metadata()?.len();
+        if on_disk != indexed as u64 * INDEX_ENTRY_LEN || self.index.len() != indexed {
+            self.write_index(indexed)?;
+        }
+        Ok(())
+    }
 
-        // Write length prefix (big-endian).
-        self.file.write_all(&len.to_be_bytes())?;
+    /// Append a single record.
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        // Keep track of the state a following delta is taken against.
+        let head = match record {
+            Record::Snapshot(node) | Record::State(node) => Some(node.clone()),
+            Record::Delta(patch) => match self.head.take() {
+                Some(head) => Some(patch.apply_to(head)?),
+                None => None,
+            },
+            Record::Input(..) => self.head.take(),
+        };
 
-        // Then write the raw bytes.
-        self.file.write_all(&bytes)?;
+        self.write(&record.encode())?;
+        self.since_keyframe = match record {
+            Record::Snapshot(_) | Record::State(_) => 0,
+            Record::Delta(_) => self.since_keyframe + 1,
+            Record::Input(..) => self.since_keyframe,
+        };
+        self.head = head;
+        Ok(())
+    }
+
+    /// Frame and write one encoded record at the end of the log.
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if bytes.len() > MAX_FRAME as usize {
+            return Err(invalid(format!(
+                "frame of {} bytes exceeds the {} byte limit",
+                bytes.len(),
+                MAX_FRAME
+            )));
+        }
+
+        // Length prefix and checksum (big-endian), then the raw bytes,
+        // in a single write to keep the window for a torn frame small.
+        let frame = frame(bytes);
+
+        // Seek to end to keep it append-only.
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&frame)?;
 
         // Ensure it's on disk (optional but nice for durability).
         self.file.flush()?;
 
+        // Index it only once the frame itself is written.
+        let offset = self.end;
+        self.end += frame.len() as u64;
+        self.index.push(offset);
+        self.index_file.seek(SeekFrom::End(0))?;
+        self.index_file.write_all(&offset.to_be_bytes())?;
+        self.index_file.flush()?;
+
         Ok(())
     }
 
-    /// Create an iterator over all frames from the beginning.
+    /// Append a Node state, as a delta from the previous state when that
+    /// is smaller and a full state is not yet due.
+    pub fn append_frame(&mut self, node: &Node) -> io::Result<()> {
+        let delta = match &self.head {
+            Some(head) if self.since_keyframe < KEYFRAME_INTERVAL => {
+                Some(Record::Delta(head.diff(node)))
+            }
+            _ => None,
+        };
+        let full = Record::State(node.clone());
+        match delta {
+            Some(delta) if delta.encode().len() < full.encode().len() => self.append(&delta),
+            _ => self.append(&full),
+        }
+    }
+
+    /// Append a Node that starts a new history.
+    pub fn append_snapshot(&mut self, node: &Node) -> io::Result<()> {
+        self.append(&Record::Snapshot(node.clone()))
+    }
+
+    /// Journal an input about to be folded into the node.
+    pub fn append_input(&mut self, source: Source, input: &Node) -> io::Result<()> {
+        self.append(&Record::Input(source, input.clone()))
+    }
+
+    /// Flush written frames all the way to disk (fsync).
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_all()?;
+        self.index_file.sync_all()
+    }
+
+    /// Number of frames in the log.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Read frame number `n`, if the log has that many frames.
+    pub fn get(&mut self, n: usize) -> io::Result<Option<Record>> {
+        let Some(&pos) = self.index.get(n) else {
+            return Ok(None);
+        };
+        match scan(&mut self.file, pos, self.end)? {
+            Scan::Frame { payload, .. } => Record::decode(&payload).map(Some),
+            Scan::Corrupt { .. } => Err(invalid(format!(
+                "checksum mismatch in frame {} at offset {}",
+                n, pos
+            ))),
+            Scan::End | Scan::Torn => Err(invalid(format!(
+                "index points past the log for frame {}",
+                n
+            ))),
+        }
+    }
+
+    /// Read the final frame, if any.
+    pub fn last(&mut self) -> io::Result<Option<Record>> {
+        match self.len() {
+            0 => Ok(None),
+            len => self.get(len - 1),
+        }
+    }
+
+    /// The full node state recorded by frame `n`, rebuilding a delta from
+    /// the nearest full state before it. `None` for an input frame.
+    pub fn state_at(&mut self, n: usize) -> io::Result<Option<Node>> {
+        let mut deltas = Vec::new();
+        let mut at = n;
+        let base = loop {
+            match self.get(at)? {
+                Some(Record::Snapshot(node) | Record::State(node)) => break node,
+                Some(Record::Delta(patch)) => deltas.push(patch),
+                Some(Record::Input(..)) if at == n => return Ok(None),
+                Some(Record::Input(..)) => {}
+                None => return Ok(None),
+            }
+            at = at
+                .checked_sub(1)
+                .ok_or_else(|| invalid(format!("delta frame {} has no full state before it", n)))?;
+        };
+        deltas
+            .iter()
+            .rev()
+            .try_fold(base, |node, patch| patch.apply_to(node))
+            .map(Some)
+    }
+
+    /// Read back the most recent readable node state, if any.
+    ///
+    /// Walks backwards from the end; states that cannot be read or
+    /// rebuilt are reported and skipped.
+    pub fn last_state(&mut self) -> io::Result<Option<Node>> {
+        for n in (0..self.len()).rev() {
+            match self.state_at(n) {
+                Ok(Some(node)) => return Ok(Some(node)),
+                Ok(None) => {}
+                Err(e) => eprintln!("[STORE] Skipping unreadable frame {}: {}", n, e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Size in bytes of frame `n`, header included.
+    fn frame_len(&self, n: usize) -> u64 {
+        let next = self.index.get(n + 1).copied().unwrap_or(self.end);
+        next - self.index[n]
+    }
+
+    /// Shrink the log to what `retention` keeps.
+    ///
+    /// The new log starts with a snapshot of the node state at the start of
+    /// the kept tail, followed by the tail frames unchanged, so it still
+    /// replays. It is written beside the log, fsynced and renamed over it.
+    pub fn compact(&mut self, retention: Retention) -> io::Result<Compaction> {
+        let len = self.len();
+        let mut compaction = Compaction {
+            frames_before: len,
+            frames_after: len,
+            bytes_before: self.end,
+            bytes_after: self.end,
+        };
+
+        // Oldest frame the policy keeps.
+        let mut keep_from = len;
+        let mut kept_bytes = 0;
+        while keep_from > 0 {
+            let size = self.frame_len(keep_from - 1);
+            let too_many = retention.frames.is_some_and(|n| len - keep_from >= n);
+            let too_big = retention.bytes.is_some_and(|n| kept_bytes + size > n);
+            if too_many || too_big {
+                break;
+            }
+            kept_bytes += size;
+            keep_from -= 1;
+        }
+
+        // The tail must open with a full state: the first one it keeps,
+        // or else the newest one before it.
+        let mut base = None;
+        for n in (keep_from..len).chain((0..keep_from).rev()) {
+            if let Ok(Some(node)) = self.state_at(n) {
+                base = Some((n, node));
+                break;
+            }
+        }
+        let Some((base, node)) = base else {
+            return Ok(compaction);
+        };
+        if base == 0 && matches!(self.get(0)?, Some(Record::Snapshot(_))) {
+            return Ok(compaction);
+        }
+
+        let mut tmp_path = OsString::from(self.path.as_os_str());
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+        {
+            let mut tmp = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp_path)?;
+            tmp.write_all(MAGIC)?;
+            tmp.write_all(&[FORMAT_VERSION])?;
+            tmp.write_all(&frame(&Record::Snapshot(node).encode()))?;
+
+            // Everything after the base frame is copied verbatim.
+            let tail = self.index.get(base + 1).copied().unwrap_or(self.end);
+            self.file.seek(SeekFrom::Start(tail))?;
+            io::copy(&mut (&self.file).take(self.end - tail), &mut tmp)?;
+            tmp.sync_all()?;
+        }
+
+        // The old index will not match the new log: drop it before the
+        // rename, so a crash in between cannot leave it beside the new log.
+        fs::remove_file(index_path(&self.path))?;
+        let renamed = fs::rename(&tmp_path, &self.path);
+
+        // Persist the rename itself; not every platform can open a directory.
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+
+        // Reopen either way, so this handle never writes to a replaced file
+        // or a removed index.
+        *self = Store::open(&self.path)?;
+        renamed?;
+
+        compaction.frames_after = self.len();
+        compaction.bytes_after = self.end;
+        println!(
+            "[STORE] Compacted {} → {} frames ({} → {} bytes)",
+            compaction.frames_before,
+            compaction.frames_after,
+            compaction.bytes_before,
+            compaction.bytes_after
+        );
+        Ok(compaction)
+    }
+
+    /// Iterate over every record from the newest to the oldest.
+    pub fn records_rev(&mut self) -> RevRecordIter<'_> {
+        let next = self.len();
+        RevRecordIter { store: self, next }
+    }
+
+    /// Create an iterator over all node states from the beginning, with
+    /// delta frames rebuilt into full states.
     pub fn iter(&mut self) -> io::Result<FrameIter> {
-        // Rewind to start of file for reading
-        self.file.seek(SeekFrom::Start(0))?;
         Ok(FrameIter {
+            records: self.records()?,
+            current: None,
+        })
+    }
+
+    /// Create an iterator over every record, inputs included.
+    pub fn records(&mut self) -> io::Result<RecordIter> {
+        Ok(RecordIter {
             file: self.file.try_clone()?,
+            pos: HEADER_LEN,
+            end: self.end,
         })
     }
 }
 
-/// Iterator over frames in the log.
-pub struct FrameIter {
+/// Iterator over all records in the log.
+///
+/// A frame with a bad checksum yields an `InvalidData` error and iteration
+/// moves on to the next frame.
+pub struct RecordIter {
     file: File,
+    pos: u64,
+    end: u64,
 }
 
-impl Iterator for FrameIter {
-    type Item = io::Result<Node>;
+impl Iterator for RecordIter {
+    type Item = io::Result<Record>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut len_buf = [0u8; 4];
-
-        // Try to read the length prefix
-        match self.file.read_exact(&mut len_buf) {
-            Ok(()) => {}
+        let pos = self.pos;
+        match scan(&mut self.file, pos, self.end) {
+            Ok(Scan::Frame { payload, next }) => {
+                self.pos = next;
+                Some(Record::decode(&payload))
+            }
+            Ok(Scan::Corrupt { next }) => {
+                self.pos = next;
+                Some(Err(invalid(format!(
+                    "checksum mismatch in frame at offset {}",
+                    pos
+                ))))
+            }
+            Ok(Scan::End) => None,
+            Ok(Scan::Torn) => {
+                self.pos = self.end;
+                Some(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("torn frame at offset {}", pos),
+                )))
+            }
             Err(e) => {
-                // If we hit EOF cleanly, stop the iteration
-                if e.kind() == io::ErrorKind::UnexpectedEof {
-                    return None;
-                } else {
-                    return Some(Err(e));
-                }
+                self.pos = self.end;
+                Some(Err(e))
             }
         }
+    }
+}
 
-        let len = u32::from_be_bytes(len_buf) as usize;
-        let mut data = vec![0u8; len];
+/// Iterator over records from the newest to the oldest, through the index.
+pub struct RevRecordIter<'a> {
+    store: &'a mut Store,
+    next: usize,
+}
 
-        if let Err(e) = self.file.read_exact(&mut data) {
-            return Some(Err(e));
-        }
+impl Iterator for RevRecordIter<'_> {
+    type Item = io::Result<Record>;
 
-        // data: Vec<u8> -> Bits -> Node
-        let bits: BitVec<u8, Msb0> = BitVec::from_slice(&data);
-        let node = Node::from(bits);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next = self.next.checked_sub(1)?;
+        self.store.get(self.next).transpose()
+    }
+}
+
+/// Iterator over node states (snapshots and derived states) in the log.
+///
+/// Delta frames are applied to the state before them. After an unreadable
+/// frame, deltas fail until the next full state.
+pub struct FrameIter {
+    records: RecordIter,
+    current: Option<Node>,
+}
 
-        Some(Ok(node))
+impl Iterator for FrameIter {
+    type Item = io::Result<Node>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = match self.records.next()? {
+                Ok(Record::Snapshot(node)) | Ok(Record::State(node)) => Ok(node),
+                Ok(Record::Delta(patch)) => match self.current.take() {
+                    Some(base) => patch.apply_to(base),
+                    None => Err(invalid("delta frame without a state before it")),
+                },
+                Ok(Record::Input(..)) => continue,
+                Err(e) => Err(e),
+            };
+            self.current = node.as_ref().ok().cloned();
+            return Some(node);
+        }
     }
 }
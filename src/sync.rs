@@ -0,0 +1,277 @@
+//! Anti-entropy sync: obtaining a verified copy of a peer's state.
+//!
+//! Peers advertise the root digest of their state. To copy a state, a node
+//! asks for the subtrees at a set of paths; the peer answers each with the
+//! digests of its two children or, for a small subtree, the subtree itself.
+//! Every answer is checked against the digest it was expected to have, so
+//! the finished copy is exactly the advertised state.
+//!
+//! Subtrees the node already holds — in its own state, or an older copy of
+//! the peer's — are matched by digest and taken locally, so only the parts
+//! that differ cross the network.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+};
+
+use crate::{
+    digest::{Digest, MerkleTree},
+    node::{Bits, Node},
+};
+
+/// Subtrees of at most this many leaves are sent whole instead of as digests.
+pub const INLINE_LEAVES: usize = 64;
+
+/// Most bytes of paths a single request asks for, keeping the request and
+/// its replies well within a datagram.
+pub const REQUEST_BUDGET: usize = 512;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// The answer for one requested path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncEntry {
+    /// The subtree is a compound with these child digests.
+    Children(Digest, Digest),
+    /// The whole subtree.
+    Subtree(Node),
+}
+
+/// Whether `node` has at most `limit` leaves, without counting past it.
+fn at_most(node: &Node, limit: usize) -> bool {
+    let mut leaves = 0;
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        match node {
+            Node::Bit(_) => {
+                leaves += 1;
+                if leaves > limit {
+                    return false;
+                }
+            }
+            Node::Compound(compound) => {
+                stack.push(compound.right());
+                stack.push(compound.left());
+            }
+        }
+    }
+    true
+}
+
+/// Bytes `path` takes up in a request: its length, then its bits.
+fn path_cost(path: &Bits) -> usize {
+    2 + path.len().div_ceil(8)
+}
+
+/// Answer a request for `paths` of `node`, whose digests are `tree`.
+///
+/// Paths outside the tree and repeated paths are skipped. A request over
+/// [`REQUEST_BUDGET`] is refused with `InvalidData`, so a small request
+/// never draws a large answer.
+pub fn answer(
+    node: &Node,
+    tree: &MerkleTree,
+    paths: &[Bits],
+) -> io::Result<Vec<(Bits, SyncEntry)>> {
+    let cost: usize = paths.iter().map(path_cost).sum();
+    if cost > REQUEST_BUDGET {
+        return Err(invalid(format!(
+            "request of {} bytes of paths is over the {} byte budget",
+            cost, REQUEST_BUDGET
+        )));
+    }
+
+    let mut answered = BTreeSet::new();
+    let mut entries = Vec::with_capacity(paths.len());
+    for path in paths {
+        if !answered.insert(path) {
+            continue;
+        }
+        let Some(subtree) = node.subtree(path) else {
+            continue;
+        };
+        let entry = match tree.children(path) {
+            Some((left, right)) if !at_most(subtree, INLINE_LEAVES) => {
+                SyncEntry::Children(left, right)
+            }
+            _ => SyncEntry::Subtree(subtree.clone()),
+        };
+        entries.push((path.clone(), entry));
+    }
+    Ok(entries)
+}
+
+/// One node's progress copying a peer's state with a given root digest.
+#[derive(Debug, Clone)]
+pub struct SyncSession {
+    root: Digest,
+    /// Paths still to fetch, with the digest each must have.
+    pending: BTreeMap<Bits, Digest>,
+    /// Pending paths asked for and not yet answered.
+    requested: BTreeSet<Bits>,
+    /// Subtrees already known, by path.
+    resolved: HashMap<Bits, Node>,
+    /// Every subtree of the locally held trees, by digest.
+    index: HashMap<Digest, Node>,
+    /// Subtrees fetched over the network so far.
+    fetched: usize,
+}
+
+impl SyncSession {
+    /// Start copying the state with digest `root`, reusing any subtree of
+    /// `sources` that appears in it.
+    ///
+    /// Indexing is linear in the distinct subtrees of `sources`, however
+    /// deep they are: a subtree already indexed is not walked again.
+    pub fn new(root: Digest, sources: Vec<Node>) -> Self {
+        let mut index = HashMap::new();
+        for source in sources {
+            // Hashing the root caches every compound's digest below it.
+            source.fingerprint();
+            let mut stack = vec![source];
+            while let Some(node) = stack.pop() {
+                let digest = node.fingerprint();
+                if index.contains_key(&digest) {
+                    continue;
+                }
+                if let Node::Compound(compound) = &node {
+                    stack.push(compound.right().clone());
+                    stack.push(compound.left().clone());
+                }
+                index.insert(digest, node);
+            }
+        }
+
+        let mut session = SyncSession {
+            root,
+            pending: BTreeMap::new(),
+            requested: BTreeSet::new(),
+            resolved: HashMap::new(),
+            index,
+            fetched: 0,
+        };
+        session.expect(Bits::new(), root);
+        session
+    }
+
+    /// The root digest being copied.
+    pub fn root(&self) -> Digest {
+        self.root
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Subtrees received over the network so far.
+    pub fn fetched(&self) -> usize {
+        self.fetched
+    }
+
+    /// Record that the subtree at `path` must hash to `digest`, taking it
+    /// from a local source when one has it.
+    fn expect(&mut self, path: Bits, digest: Digest) {
+        match self.index.get(&digest) {
+            Some(subtree) => {
+                self.resolved.insert(path, subtree.clone());
+            }
+            None => {
+                self.pending.insert(path, digest);
+            }
+        }
+    }
+
+    /// The next paths to ask for: pending ones not already asked for, up to
+    /// [`REQUEST_BUDGET`] bytes of paths.
+    pub fn request(&mut self) -> Vec<Bits> {
+        let mut budget = REQUEST_BUDGET;
+        let mut paths = Vec::new();
+        for path in self.pending.keys() {
+            if self.requested.contains(path) {
+                continue;
+            }
+            let cost = path_cost(path);
+            if cost > budget {
+                break;
+            }
+            budget -= cost;
+            paths.push(path.clone());
+        }
+        self.requested.extend(paths.iter().cloned());
+        paths
+    }
+
+    /// Forget what was asked for, so the next [`request`](SyncSession::request)
+    /// asks again for everything still pending (after a lost packet).
+    pub fn retry(&mut self) {
+        self.requested.clear();
+    }
+
+    /// Take in the answer for `path`.
+    ///
+    /// Answers for paths that are not pending are ignored. An answer that
+    /// does not hash to the expected digest fails with `InvalidData`.
+    pub fn receive(&mut self, path: Bits, entry: SyncEntry) -> io::Result<()> {
+        let Some(&expected) = self.pending.get(&path) else {
+            return Ok(());
+        };
+
+        match entry {
+            SyncEntry::Children(left, right) => {
+                if Digest::compound(&left, &right) != expected {
+                    return Err(invalid(format!("children at {} do not match", path)));
+                }
+                let (mut left_path, mut right_path) = (path.clone(), path.clone());
+                left_path.push(false);
+                right_path.push(true);
+                self.expect(left_path, left);
+                self.expect(right_path, right);
+            }
+            SyncEntry::Subtree(node) => {
+                if node.fingerprint() != expected {
+                    return Err(invalid(format!("subtree at {} does not match", path)));
+                }
+                self.resolved.insert(path.clone(), node);
+            }
+        }
+
+        self.fetched += 1;
+        self.pending.remove(&path);
+        self.requested.remove(&path);
+        Ok(())
+    }
+
+    /// The copied state, once every path is resolved.
+    pub fn finish(mut self) -> Option<Node> {
+        if !self.is_complete() {
+            return None;
+        }
+
+        // Rebuild the tree from the resolved subtrees: any path without one
+        // is a compound of its two children.
+        let mut todo = vec![(Bits::new(), false)];
+        let mut built: Vec<Node> = Vec::new();
+        while let Some((path, children_built)) = todo.pop() {
+            if children_built {
+                let right = built.pop().expect("right child built");
+                let left = built.pop().expect("left child built");
+                built.push(Node::compound(left, right));
+            } else if let Some(subtree) = self.resolved.remove(&path) {
+                built.push(subtree);
+            } else {
+                let (mut left, mut right) = (path.clone(), path.clone());
+                left.push(false);
+                right.push(true);
+                todo.push((path, true));
+                todo.push((right, false));
+                todo.push((left, false));
+            }
+        }
+
+        let node = built.pop().expect("one root");
+        (node.fingerprint() == self.root).then_some(node)
+    }
+}
@@ -0,0 +1,327 @@
+//! Framed UDP messages exchanged between `hey,` nodes.
+//!
+//! Envelope: `[version u8][type u8][u32 payload len][payload]`, big-endian.
+//!
+//! | type          | payload                          |
+//! |---------------|----------------------------------|
+//! | `ANNOUNCE`    | sender's Node, [`codec`] encoded |
+//! | `ACK`         | empty                            |
+//! | `CHAT`        | raw chat bytes (non-empty)       |
+//! | `STATE_DELTA` | [`NodePatch`], encoded           |
+//! | `PING`/`PONG` | `u64` nonce                      |
+//! | `BYE`         | empty                            |
+//! | `ROOT`        | sender's 32 byte root [`Digest`] |
+//! | `SYNC_REQUEST`| root, `u16` count, paths         |
+//! | `SYNC_REPLY`  | root, `u16` count, entries       |
+//!
+//! A path is `[u16 bits][bits, zero padded to a byte]`. A sync entry is a
+//! path, then `0` and the two child digests, or `1`, a `u16` length and the
+//! [`codec`] encoded subtree.
+//!
+//! Anything that does not match exactly is rejected by [`Message::decode`].
+//! No packet may be longer than [`MAX_DATAGRAM`].
+
+use std::io;
+
+use crate::{
+    codec,
+    digest::Digest,
+    node::{Bits, Node, SIZE},
+    patch::NodePatch,
+    sync::SyncEntry,
+};
+
+/// Current wire protocol version, the first byte of every packet.
+pub const VERSION: u8 = 1;
+
+/// Size of the fixed envelope preceding the payload.
+pub const HEADER_LEN: usize = 6;
+
+/// The largest packet that fits one UDP datagram over IPv4.
+pub const MAX_DATAGRAM: usize = 65_507;
+
+pub const ANNOUNCE: u8 = 0x01;
+pub const ACK: u8 = 0x02;
+pub const CHAT: u8 = 0x03;
+pub const STATE_DELTA: u8 = 0x04;
+pub const PING: u8 = 0x05;
+pub const PONG: u8 = 0x06;
+pub const BYE: u8 = 0x07;
+pub const ROOT: u8 = 0x08;
+pub const SYNC_REQUEST: u8 = 0x09;
+pub const SYNC_REPLY: u8 = 0x0A;
+
+const CHILDREN: u8 = 0;
+const SUBTREE: u8 = 1;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Split `n` bytes off the front of `rest`.
+fn take<'a>(rest: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if rest.len() < n {
+        return Err(invalid("truncated payload"));
+    }
+    let (head, tail) = rest.split_at(n);
+    *rest = tail;
+    Ok(head)
+}
+
+fn take_u16(rest: &mut &[u8]) -> io::Result<usize> {
+    let bytes = take(rest, 2)?.try_into().expect("2 bytes");
+    Ok(u16::from_be_bytes(bytes) as usize)
+}
+
+fn take_digest(rest: &mut &[u8]) -> io::Result<Digest> {
+    let bytes = take(rest, 32)?.try_into().expect("32 bytes");
+    Ok(Digest::from_bytes(bytes))
+}
+
+fn put_path(out: &mut Vec<u8>, path: &Bits) {
+    out.extend_from_slice(&(path.len() as u16).to_be_bytes());
+    out.extend_from_slice(path.as_raw_slice());
+}
+
+fn take_path(rest: &mut &[u8]) -> io::Result<Bits> {
+    let bits = take_u16(rest)?;
+    let mut path = Bits::from_slice(take(rest, bits.div_ceil(8))?);
+    if path[bits..].any() {
+        return Err(invalid("non-zero padding in path"));
+    }
+    path.truncate(bits);
+    Ok(path)
+}
+
+fn put_entry(out: &mut Vec<u8>, path: &Bits, entry: &SyncEntry) {
+    put_path(out, path);
+    match entry {
+        SyncEntry::Children(left, right) => {
+            out.push(CHILDREN);
+            out.extend_from_slice(left.as_bytes());
+            out.extend_from_slice(right.as_bytes());
+        }
+        SyncEntry::Subtree(node) => {
+            let node = codec::encode(node);
+            out.push(SUBTREE);
+            out.extend_from_slice(&(node.len() as u16).to_be_bytes());
+            out.extend_from_slice(&node);
+        }
+    }
+}
+
+fn take_entry(rest: &mut &[u8]) -> io::Result<(Bits, SyncEntry)> {
+    let path = take_path(rest)?;
+    let entry = match take(rest, 1)?[0] {
+        CHILDREN => SyncEntry::Children(take_digest(rest)?, take_digest(rest)?),
+        SUBTREE => {
+            let len = take_u16(rest)?;
+            SyncEntry::Subtree(codec::decode(take(rest, len)?)?)
+        }
+        other => return Err(invalid(format!("unknown sync entry kind {}", other))),
+    };
+    Ok((path, entry))
+}
+
+/// Parse `[u16 count]` items with `item`, requiring the payload to end there.
+fn take_list<T>(mut rest: &[u8], item: impl Fn(&mut &[u8]) -> io::Result<T>) -> io::Result<Vec<T>> {
+    let count = take_u16(&mut rest)?;
+    let items = (0..count)
+        .map(|_| item(&mut rest))
+        .collect::<io::Result<Vec<T>>>()?;
+    if !rest.is_empty() {
+        return Err(invalid("trailing data after list"));
+    }
+    Ok(items)
+}
+
+/// A single packet on the mesh.
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// "I am here": the sender's current node state.
+    Announce(Node),
+    /// Reply to an announce, so the announcer learns us as a peer.
+    Ack,
+    /// Chat bytes typed into a peer.
+    Chat(Vec<u8>),
+    /// The change an input made to the sender's state.
+    StateDelta(NodePatch),
+    Ping(u64),
+    Pong(u64),
+    /// The sender is leaving the mesh.
+    Bye,
+    /// The root digest of the sender's current state.
+    Root(Digest),
+    /// Ask for the subtrees at `paths` of the state with digest `root`.
+    SyncRequest {
+        root: Digest,
+        paths: Vec<Bits>,
+    },
+    /// Answers to a [`SyncRequest`](Message::SyncRequest) for `root`.
+    SyncReply {
+        root: Digest,
+        entries: Vec<(Bits, SyncEntry)>,
+    },
+}
+
+impl Message {
+    /// The type byte written in the envelope.
+    pub fn kind(&self) -> u8 {
+        match self {
+            Message::Announce(_) => ANNOUNCE,
+            Message::Ack => ACK,
+            Message::Chat(_) => CHAT,
+            Message::StateDelta(_) => STATE_DELTA,
+            Message::Ping(_) => PING,
+            Message::Pong(_) => PONG,
+            Message::Bye => BYE,
+            Message::Root(_) => ROOT,
+            Message::SyncRequest { .. } => SYNC_REQUEST,
+            Message::SyncReply { .. } => SYNC_REPLY,
+        }
+    }
+
+    /// Serialize into a framed packet.
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = match self {
+            Message::Announce(node) => codec::encode(node),
+            Message::StateDelta(patch) => patch.encode(),
+            Message::Chat(data) => data.clone(),
+            Message::Ping(nonce) | Message::Pong(nonce) => nonce.to_be_bytes().to_vec(),
+            Message::Ack | Message::Bye => Vec::new(),
+            Message::Root(digest) => digest.as_bytes().to_vec(),
+            Message::SyncRequest { root, paths } => {
+                let mut out = root.as_bytes().to_vec();
+                out.extend_from_slice(&(paths.len() as u16).to_be_bytes());
+                for path in paths {
+                    put_path(&mut out, path);
+                }
+                out
+            }
+            Message::SyncReply { root, entries } => {
+                let mut out = root.as_bytes().to_vec();
+                out.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+                for (path, entry) in entries {
+                    put_entry(&mut out, path, entry);
+                }
+                out
+            }
+        };
+
+        let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+        out.push(VERSION);
+        out.push(self.kind());
+        out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        out.extend_from_slice(&payload);
+        out
+    }
+
+    /// The [`StateDelta`](Message::StateDelta) carrying `patch`, or when
+    /// that would not fit a datagram, the sender's `root` so peers sync
+    /// the new state instead.
+    pub fn state_delta(patch: NodePatch, root: Digest) -> Message {
+        let delta = Message::StateDelta(patch);
+        if delta.encode().len() > MAX_DATAGRAM {
+            return Message::Root(root);
+        }
+        delta
+    }
+
+    /// Pack `entries` answering a request for `root` into as few
+    /// [`SyncReply`](Message::SyncReply) packets as fit a datagram each.
+    pub fn sync_replies(root: Digest, entries: Vec<(Bits, SyncEntry)>) -> Vec<Message> {
+        // Envelope, root and count.
+        const OVERHEAD: usize = HEADER_LEN + 32 + 2;
+
+        let mut replies = Vec::new();
+        let mut chunk = Vec::new();
+        let mut len = OVERHEAD;
+        for (path, entry) in entries {
+            let mut bytes = Vec::new();
+            put_entry(&mut bytes, &path, &entry);
+            if !chunk.is_empty() && len + bytes.len() > SIZE {
+                replies.push(Message::SyncReply {
+                    root,
+                    entries: std::mem::take(&mut chunk),
+                });
+                len = OVERHEAD;
+            }
+            len += bytes.len();
+            chunk.push((path, entry));
+        }
+        if !chunk.is_empty() {
+            replies.push(Message::SyncReply {
+                root,
+                entries: chunk,
+            });
+        }
+        replies
+    }
+
+    /// Parse a framed packet, rejecting anything malformed with `InvalidData`.
+    pub fn decode(packet: &[u8]) -> io::Result<Message> {
+        if packet.len() < HEADER_LEN {
+            return Err(invalid(format!("short packet ({} bytes)", packet.len())));
+        }
+
+        let (header, payload) = packet.split_at(HEADER_LEN);
+        if header[0] != VERSION {
+            return Err(invalid(format!("unsupported wire version {}", header[0])));
+        }
+
+        let len = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+        if len != payload.len() {
+            return Err(invalid(format!(
+                "payload length {} does not match header length {}",
+                payload.len(),
+                len
+            )));
+        }
+
+        let nonce = || -> io::Result<u64> {
+            let bytes: [u8; 8] = payload
+                .try_into()
+                .map_err(|_| invalid("nonce payload must be 8 bytes"))?;
+            Ok(u64::from_be_bytes(bytes))
+        };
+        let empty = |message: Message| -> io::Result<Message> {
+            if payload.is_empty() {
+                Ok(message)
+            } else {
+                Err(invalid("unexpected payload"))
+            }
+        };
+
+        match header[1] {
+            ANNOUNCE => Ok(Message::Announce(codec::decode(payload)?)),
+            ACK => empty(Message::Ack),
+            CHAT if payload.is_empty() => Err(invalid("empty chat")),
+            CHAT => Ok(Message::Chat(payload.to_vec())),
+            STATE_DELTA => Ok(Message::StateDelta(NodePatch::decode(payload)?)),
+            PING => Ok(Message::Ping(nonce()?)),
+            PONG => Ok(Message::Pong(nonce()?)),
+            BYE => empty(Message::Bye),
+            ROOT => {
+                let mut rest = payload;
+                let digest = take_digest(&mut rest)?;
+                if !rest.is_empty() {
+                    return Err(invalid("root payload must be 32 bytes"));
+                }
+                Ok(Message::Root(digest))
+            }
+            SYNC_REQUEST => {
+                let mut rest = payload;
+                let root = take_digest(&mut rest)?;
+                let paths = take_list(rest, take_path)?;
+                Ok(Message::SyncRequest { root, paths })
+            }
+            SYNC_REPLY => {
+                let mut rest = payload;
+                let root = take_digest(&mut rest)?;
+                let entries = take_list(rest, take_entry)?;
+                Ok(Message::SyncReply { root, entries })
+            }
+            other => Err(invalid(format!("unknown message type {:#04x}", other))),
+        }
+    }
+}
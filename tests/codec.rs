@@ -0,0 +1,47 @@
+mod common;
+
+use hey::{codec, Bits, Node};
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn encode_decode_roundtrip(node in common::node()) {
+        let bytes = codec::encode(&node);
+        let decoded = codec::decode(&bytes).unwrap();
+        prop_assert_eq!(codec::encode(&decoded), bytes);
+        prop_assert_eq!(Bits::from(&decoded), Bits::from(&node));
+        prop_assert_eq!(decoded.size(), node.size());
+    }
+
+    #[test]
+    fn truncated_encodings_are_rejected(node in common::node()) {
+        let bytes = codec::encode(&node);
+        for len in 0..bytes.len() {
+            prop_assert!(codec::decode(&bytes[..len]).is_err());
+        }
+    }
+}
+
+#[test]
+fn single_bit_encoding() {
+    // version, then `0 1` padded with zeros.
+    assert_eq!(
+        codec::encode(&Node::Bit(true)),
+        vec![codec::VERSION, 0b0100_0000]
+    );
+}
+
+#[test]
+fn rejects_unknown_version_and_trailing_data() {
+    let mut bytes = codec::encode(&Node::Bit(false));
+    bytes[0] = codec::VERSION + 1;
+    assert!(codec::decode(&bytes).is_err());
+
+    let mut bytes = codec::encode(&Node::Bit(false));
+    bytes.push(0);
+    assert!(codec::decode(&bytes).is_err());
+
+    let mut bytes = codec::encode(&Node::Bit(false));
+    bytes[1] |= 1;
+    assert!(codec::decode(&bytes).is_err());
+}
@@ -0,0 +1,26 @@
+#![allow(dead_code)]
+
+use hey::Node;
+use proptest::prelude::*;
+
+/// Arbitrary, possibly unbalanced, Node trees.
+pub fn node() -> impl Strategy<Value = Node> {
+    any::<bool>()
+        .prop_map(Node::Bit)
+        .prop_recursive(12, 512, 2, |inner| {
+            (inner.clone(), inner).prop_map(|(l, r)| Node::compound(l, r))
+        })
+}
+
+/// A fresh log path under the system temp dir, unique to this process and `name`.
+pub fn temp_path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("hey-{}-{}", std::process::id(), name));
+    remove_log(&path);
+    path
+}
+
+/// Remove a log and its sidecar files.
+pub fn remove_log(path: &std::path::Path) {
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(hey::store::index_path(path));
+}
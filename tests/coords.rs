@@ -0,0 +1,163 @@
+use std::f64::consts::E;
+
+use hey::coords::{self, code, f, g, h, superpose, weight};
+use hey::{Atom, Bits, Node};
+
+/// Whether `actual` is within `1e-12` of `expected`, relatively.
+fn close(actual: f64, expected: f64) -> bool {
+    (actual - expected).abs() <= 1e-12 * expected.abs()
+}
+
+#[track_caller]
+fn assert_close(actual: f64, expected: f64) {
+    assert!(close(actual, expected), "{} is not {}", actual, expected);
+}
+
+#[test]
+fn maps_match_known_values() {
+    // Computed independently at 30 digits.
+    assert_close(f(2.0), 1.331_504_331_825_020_8);
+    assert_close(g(2.0), 2.565_323_759_492_731_4);
+    assert_close(f(0.5), 4.120_632_393_371_675e-6);
+    assert_close(g(0.5), 0.023_090_389_875_362_173);
+    assert_close(f(10.0), 1.012_045_670_861_294_7);
+    assert_close(g(10.0), 1.869_942_200_708_239);
+    assert_close(h(2.0), 0.735_758_882_342_884_6);
+
+    // Fixed points and peaks.
+    let peak = E.powf(1.0 / E);
+    assert_close(f(peak), peak);
+    assert_close(g(E), E);
+    assert_eq!(f(1.0), 1.0);
+    assert_eq!(g(1.0), 1.0);
+    assert_eq!(h(1.0), 1.0);
+}
+
+#[test]
+fn domain_edges_take_their_limits() {
+    assert_eq!(f(0.0), 0.0);
+    assert_eq!(g(0.0), 0.0);
+    assert_eq!(f(f64::INFINITY), 1.0);
+    assert_eq!(g(f64::INFINITY), 1.0);
+    assert_eq!(h(0.0), 0.0);
+    assert_eq!(h(f64::INFINITY), 0.0);
+    assert_eq!(weight(f64::INFINITY), f64::INFINITY);
+
+    // No overflow on the way out, no spurious NaN on the way in.
+    assert_close(f(f64::MAX), 1.0);
+    assert_close(g(1e300), 1.0);
+    assert_eq!(f(f64::MIN_POSITIVE), 0.0);
+    assert_eq!(h(1e300), 0.0);
+    assert!(h(1e-300) > 0.0);
+
+    assert!(f(-1.0).is_nan());
+    assert!(g(-1.0).is_nan());
+    assert!(h(-1.0).is_nan());
+    assert!(h(f64::NAN).is_nan());
+}
+
+#[test]
+fn weight_keeps_precision_near_the_peak() {
+    // −ln H(1 + ε) ≈ ε²/2: nothing left of it by subtracting.
+    assert_close(weight(1.0 + 2f64.powi(-30)), 4.336_808_687_249_044e-19);
+    assert_eq!(weight(1.0), 0.0);
+    // Either side of where the series hands over to expm1.
+    assert_close(weight(1.005), 1.245_848_896_092_585_8e-5);
+    assert_close(weight(1.02), 1.973_727_038_202_873e-4);
+    for u in [0.5, 0.9, 1.5, 3.0] {
+        assert_close(weight(u), -h(u).ln());
+    }
+}
+
+#[test]
+fn atoms_have_known_coordinates() {
+    let cases = [
+        (
+            0,
+            [
+                0.722_333_930_504_883,
+                0.117_590_161_295_367_4,
+                0.294_038_312_111_820_8,
+                0.284_182_093_847_367,
+                0.595_661_027_845_243_9,
+                0.518_083_518_939_804_6,
+            ],
+        ),
+        (
+            128,
+            [
+                1.444_667_861_009_766,
+                1.444_667_861_009_766,
+                1.998_107_789_670_767_5,
+                0.926_085_774_257_146_4,
+                0.736_454_987_205_592_1,
+                0.305_907_162_115_421_1,
+            ],
+        ),
+        (
+            255,
+            [
+                2.873_731_643_371_294,
+                1.176_782_847_378_485_5,
+                2.714_234_159_434_634,
+                0.986_098_991_039_885_3,
+                0.488_837_906_799_394,
+                0.715_724_323_396_396_5,
+            ],
+        ),
+    ];
+    for (byte, expected) in cases {
+        let atom = Atom::new(byte);
+        assert_eq!(atom.byte, byte);
+        let actual = [
+            atom.x,
+            atom.u_int,
+            atom.u_ext,
+            atom.d_int,
+            atom.d_ext,
+            atom.weight,
+        ];
+        for (actual, expected) in actual.into_iter().zip(expected) {
+            assert_close(actual, expected);
+        }
+    }
+
+    // Codes rise with the atom, one octave either side of the peak of f.
+    assert!((0..255).all(|c| code(c) < code(c + 1)));
+    assert_close(code(0) * 2.0, code(128));
+}
+
+#[test]
+fn superposition_is_a_weighted_barycenter() {
+    assert_eq!(superpose([]), None);
+    assert_eq!(superpose([7]), Some(Atom::new(7).d_int));
+
+    let (a, b) = (Atom::new(0), Atom::new(255));
+    let expected = (a.d_int * (-a.weight).exp() + b.d_int * (-b.weight).exp())
+        / ((-a.weight).exp() + (-b.weight).exp());
+    assert_close(superpose([0, 255]).unwrap(), expected);
+
+    let d = superpose(0..=255).unwrap();
+    assert!(d > 0.0 && d < 1.0);
+}
+
+#[test]
+fn same_atoms_give_the_same_address() {
+    let bytes: Vec<u8> = (0..200).map(|i| (i * 37 % 251) as u8).collect();
+    let mut shuffled = bytes.clone();
+    shuffled.reverse();
+    shuffled.rotate_left(17);
+    assert_eq!(superpose(bytes.clone()), superpose(shuffled.clone()));
+
+    // Multiplicity counts.
+    let mut doubled = bytes.clone();
+    doubled.push(bytes[0]);
+    assert_ne!(superpose(doubled), superpose(bytes.clone()));
+
+    let node = Node::from(Bits::from_vec(bytes.clone()));
+    assert_eq!(coords::atoms(&node), bytes);
+    assert_eq!(
+        coords::coordinate(&node),
+        coords::coordinate(&Node::from(Bits::from_vec(shuffled)))
+    );
+}
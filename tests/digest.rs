@@ -0,0 +1,82 @@
+mod common;
+
+use hey::{Bits, MerkleTree, Node};
+use proptest::prelude::*;
+
+/// Every path that reaches a subtree of `node`, in pre-order.
+fn paths(node: &Node) -> Vec<Bits> {
+    let mut out = Vec::new();
+    let mut todo = vec![Bits::new()];
+    while let Some(path) = todo.pop() {
+        if let Some(Node::Compound(_)) = node.subtree(&path) {
+            let (mut left, mut right) = (path.clone(), path.clone());
+            left.push(false);
+            right.push(true);
+            todo.push(right);
+            todo.push(left);
+        }
+        out.push(path);
+    }
+    out
+}
+
+proptest! {
+    #[test]
+    fn every_subtree_digest_matches_its_fingerprint(node in common::node()) {
+        let tree = MerkleTree::new(&node);
+        prop_assert_eq!(tree.root(), node.fingerprint());
+        for path in paths(&node) {
+            let subtree = node.subtree(&path).unwrap();
+            prop_assert_eq!(tree.get(&path), Some(subtree.fingerprint()));
+            match subtree {
+                Node::Compound(compound) => prop_assert_eq!(
+                    tree.children(&path),
+                    Some((compound.left().fingerprint(), compound.right().fingerprint()))
+                ),
+                Node::Bit(_) => prop_assert_eq!(tree.children(&path), None),
+            }
+        }
+    }
+
+    #[test]
+    fn patching_the_mismatches_converges(a in common::node(), b in common::node()) {
+        let mismatches = MerkleTree::new(&a).mismatches(&MerkleTree::new(&b));
+        prop_assert_eq!(mismatches.is_empty(), a == b);
+
+        let mut patched = a.clone();
+        let mut patch = hey::NodePatch::new();
+        for path in mismatches {
+            patch.replace(path.clone(), b.subtree(&path).unwrap().clone());
+        }
+        patched.apply(&patch).unwrap();
+        prop_assert_eq!(patched, b);
+    }
+}
+
+#[test]
+fn one_flipped_leaf_is_one_mismatch() {
+    let mut bits = Bits::from_slice(b"hey,");
+    let a = Node::from(bits.clone());
+    let flipped = !bits[9];
+    bits.set(9, flipped);
+    let b = Node::from(bits);
+
+    let mismatches = MerkleTree::new(&a).mismatches(&MerkleTree::new(&b));
+    assert_eq!(
+        mismatches,
+        a.diff(&b)
+            .changes()
+            .iter()
+            .map(|(p, _)| p.clone())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(mismatches[0].len(), 5);
+
+    let tree = MerkleTree::new(&a);
+    assert_eq!(
+        tree.get(&mismatches[0][..4]).unwrap(),
+        a.subtree(&mismatches[0][..4]).unwrap().fingerprint()
+    );
+    assert!(tree.get(&Bits::repeat(false, 6)).is_none());
+    assert_eq!(a.fingerprint().short().len(), 16);
+}
@@ -0,0 +1,200 @@
+use hey::{
+    entropy, Bits, EntropySource, HashStream, Node, OsRandom, Recorded, Replayed, UniversalEntropy,
+};
+use rand_core::RngCore;
+
+/// Fractional bits of e^(1/e), computed independently at high precision.
+const FIRST_64: u64 = 0x71d5_c0c0_9e85_2444;
+/// Fractional bits 9985..=10048 of e^(1/e).
+const AT_9984: u64 = 0x2e52_6e84_8cec_0f1a;
+
+fn word(bits: &Bits) -> u64 {
+    bits.iter().by_vals().fold(0, |w, b| (w << 1) | b as u64)
+}
+
+#[test]
+fn stream_is_the_expansion_of_e_to_the_one_over_e() {
+    let mut entropy = UniversalEntropy::new();
+    assert_eq!(word(&entropy.bits_to_pos(64)), FIRST_64);
+    assert_eq!(word(&entropy.bits_range(9984, 64)), AT_9984);
+    assert_eq!(
+        word(&entropy::expansion(10048)[9984..].to_bitvec()),
+        AT_9984
+    );
+}
+
+#[test]
+fn growing_the_stream_keeps_its_prefix() {
+    let mut entropy = UniversalEntropy::new();
+    let prefix = entropy.bits_to_pos(1000);
+    assert!(entropy.bit(50_000) || !entropy.bit(50_000));
+    assert_eq!(entropy.bits_to_pos(1000), prefix);
+    assert_eq!(
+        UniversalEntropy::new().bits_range(40_000, 100),
+        entropy.bits_range(40_000, 100)
+    );
+}
+
+#[test]
+fn window_stays_under_its_capacity() {
+    let mut reference = UniversalEntropy::new();
+    let mut entropy = UniversalEntropy::with_capacity(4096);
+
+    // Straight to a far position, then back to the start.
+    assert_eq!(entropy.bit(200_000), reference.bit(200_000));
+    assert!(entropy.held().contains(&200_000));
+    assert!(entropy.held().len() <= 4096);
+    assert_eq!(word(&entropy.bits_to_pos(64)), FIRST_64);
+    assert!(entropy.held().len() <= 4096);
+
+    // A forward scan past the window.
+    for pos in (5_000..20_000).step_by(997) {
+        assert_eq!(entropy.bit(pos), reference.bit(pos));
+        assert!(entropy.held().len() <= 4096);
+    }
+
+    // A range longer than the capacity keeps only its tail.
+    assert_eq!(
+        entropy.bits_range(1000, 10_000),
+        reference.bits_range(1000, 10_000)
+    );
+    assert_eq!(entropy.held(), 6904..11_000);
+}
+
+/// Every source answers ranges and single bits alike, and repeats itself.
+fn check_source(source: &mut dyn EntropySource) {
+    let range = source.range(100, 700);
+    let bits: Bits = (100..800).map(|pos| source.bit(pos)).collect();
+    assert_eq!(range, bits);
+    assert_eq!(source.range(100, 700), range);
+    assert!(source.range(5, 0).is_empty());
+    assert_eq!(source.fingerprint(), source.fingerprint());
+}
+
+#[test]
+fn every_source_is_consistent() {
+    check_source(&mut UniversalEntropy::new());
+    check_source(&mut HashStream::new(b"hey,"));
+    check_source(&mut OsRandom::new());
+    check_source(&mut Recorded::new(HashStream::new(b"hey,")));
+    check_source(&mut Replayed::new(HashStream::new(b"hey,").range(0, 1024)));
+}
+
+#[test]
+fn sources_are_told_apart_by_fingerprint() {
+    let universal = UniversalEntropy::new().fingerprint();
+    let hashed = HashStream::new(b"hey,").fingerprint();
+    assert_ne!(universal, hashed);
+    assert_ne!(hashed, HashStream::new(b"hey!").fingerprint());
+    assert_eq!(hashed, HashStream::new(b"hey,").fingerprint());
+
+    // A fresh draw from the OS is a different stream.
+    let mut os = OsRandom::new();
+    let before = os.fingerprint();
+    os.reset();
+    assert_ne!(os.fingerprint(), before);
+
+    let mut universal_source = UniversalEntropy::new();
+    universal_source.bit(5000);
+    universal_source.reset();
+    assert_eq!(universal_source.fingerprint(), universal);
+}
+
+#[test]
+fn recorded_runs_replay_exactly() {
+    let root = Node::from(Bits::from_slice(b"hey,"));
+    let inputs = [Node::Bit(true), Node::from(Bits::from_slice(b"hi\n"))];
+
+    let mut recorded = Recorded::new(HashStream::new(b"run"));
+    let mut node = root.clone();
+    for input in &inputs {
+        node = node.reflect(&mut recorded, input);
+    }
+
+    let mut replayed = Replayed::new(recorded.recording().clone());
+    let mut again = root;
+    for input in &inputs {
+        again = again.reflect(&mut replayed, input);
+    }
+    assert_eq!(again, node);
+}
+
+#[test]
+#[should_panic(expected = "past the 8 recorded bits")]
+fn replaying_past_the_recording_panics() {
+    Replayed::new(Bits::repeat(true, 8)).bit(8);
+}
+
+#[test]
+fn bytes_and_words_read_the_stream_in_order() {
+    let mut entropy = UniversalEntropy::new();
+    assert_eq!(entropy.next_u64(), FIRST_64);
+    assert_eq!(entropy.next_u32(), word(&entropy.bits_range(64, 32)) as u32);
+    assert_eq!(entropy.position(), 96);
+
+    let mut bytes = [0u8; 40];
+    entropy.fill_bytes(&mut bytes);
+    assert_eq!(Bits::from_slice(&bytes), entropy.bits_range(96, 320));
+
+    let bits: Bits = entropy.iter().take(10).collect();
+    assert_eq!(bits, entropy.bits_range(416, 10));
+    assert_eq!(entropy.position(), 426);
+
+    entropy.seek(9984);
+    assert_eq!(entropy.next_u64(), AT_9984);
+    entropy.reset();
+    assert_eq!(entropy.position(), 0);
+
+    // Reads longer than the window still come out whole.
+    let mut small = UniversalEntropy::with_capacity(1024);
+    let mut long = vec![0u8; 1000];
+    small.fill_bytes(&mut long);
+    assert_eq!(Bits::from_vec(long), entropy.bits_to_pos(8000));
+}
+
+#[test]
+fn bytes_read_from_unaligned_positions() {
+    let mut entropy = UniversalEntropy::new();
+    let first = entropy.iter().next().expect("endless");
+    assert_eq!(first, entropy.bits_range(0, 1)[0]);
+    let mut bytes = [0u8; 4];
+    entropy.fill_bytes(&mut bytes);
+    assert_eq!(Bits::from_slice(&bytes), entropy.bits_range(1, 32));
+    assert_eq!(entropy.position(), 33);
+
+    entropy.seek(3);
+    assert_eq!(entropy.next_u32(), word(&entropy.bits_range(3, 32)) as u32);
+    assert_eq!(entropy.next_u64(), word(&entropy.bits_range(35, 64)));
+    assert_eq!(entropy.position(), 99);
+
+    entropy.seek(5);
+    assert_eq!(
+        RngCore::next_u32(&mut entropy),
+        word(&entropy.bits_range(5, 32)) as u32
+    );
+    let mut long = [0u8; 300];
+    RngCore::fill_bytes(&mut entropy, &mut long);
+    assert_eq!(Bits::from_slice(&long), entropy.bits_range(37, 2400));
+}
+
+/// Fisher–Yates, generic over any `rand_core` generator.
+fn shuffle<T>(items: &mut [T], rng: &mut impl RngCore) {
+    for i in (1..items.len()).rev() {
+        let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+#[test]
+fn drives_rand_core_consumers_deterministically() {
+    let mut a: Vec<u32> = (0..100).collect();
+    let mut b = a.clone();
+    shuffle(&mut a, &mut UniversalEntropy::new());
+    shuffle(&mut b, &mut UniversalEntropy::new());
+    assert_eq!(a, b);
+    assert_ne!(a, (0..100).collect::<Vec<_>>());
+
+    let mut sorted = a.clone();
+    sorted.sort();
+    assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+}
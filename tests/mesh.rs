@@ -0,0 +1,216 @@
+mod common;
+
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use hey::{mesh::SYNC_RETRY, peers::HEARTBEAT, Bits, Mesh, Message, Node, StopHandle, Store};
+
+/// Meshes in these tests share loopback ports and the ROOT port; one test
+/// at a time keeps stray packets out of each other's logs.
+static NET: Mutex<()> = Mutex::new(());
+
+fn net() -> MutexGuard<'static, ()> {
+    NET.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A bound mesh logging to a fresh temp log.
+fn mesh(name: &str) -> (Mesh, PathBuf) {
+    let path = common::temp_path(name);
+    let mesh = Mesh::start(Node::root(), Store::open(&path).unwrap()).unwrap();
+    (mesh, path)
+}
+
+/// Where peers see `mesh` sending from.
+fn addr(mesh: &Mesh) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], mesh.port()))
+}
+
+/// Handle every datagram `mesh` receives until it has been quiet for a
+/// moment, returning how many there were.
+fn drain(mesh: &mut Mesh) -> usize {
+    let mut handled = 0;
+    let mut quiet_since = Instant::now();
+    while quiet_since.elapsed() < Duration::from_millis(50) {
+        if mesh.poll().unwrap() {
+            handled += 1;
+            quiet_since = Instant::now();
+        } else {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    handled
+}
+
+/// Handle the next datagram `mesh` receives.
+fn receive(mesh: &mut Mesh) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !mesh.poll().unwrap() {
+        assert!(Instant::now() < deadline, "nothing received");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn stopping_before_a_bind_keeps_the_log() {
+    let path = common::temp_path("mesh-stopped");
+    let stop = StopHandle::default();
+    stop.stop();
+
+    let started = Mesh::start_with(Node::root(), Store::open(&path).unwrap(), stop);
+    assert_eq!(
+        started.err().map(|e| e.kind()),
+        Some(io::ErrorKind::Interrupted)
+    );
+
+    let mut store = Store::open(&path).unwrap();
+    assert_eq!(store.last_state().unwrap(), Some(Node::root()));
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn a_state_delta_is_folded_once() {
+    let _net = net();
+    let (mut a, a_path) = mesh("mesh-delta-a");
+    let (mut b, b_path) = mesh("mesh-delta-b");
+    b.input(&[7; 1000]).unwrap();
+
+    let before = b.node().clone();
+    let mut flipped = Bits::from(&before);
+    for i in (0..flipped.len()).step_by(97) {
+        let bit = flipped[i];
+        flipped.set(i, !bit);
+    }
+    let after = Node::from(flipped);
+    let patch = before.diff(&after);
+    assert!(patch.len() > 50);
+    let delta = Message::StateDelta(patch);
+
+    // Without the sender's state there is nothing to patch.
+    let frames = a.store().len();
+    b.send(&delta, addr(&a)).unwrap();
+    receive(&mut a);
+    assert_eq!(a.store().len(), frames);
+
+    b.send(&Message::Announce(before.clone()), addr(&a))
+        .unwrap();
+    receive(&mut a);
+    assert_eq!(a.peer_state(&addr(&b)), Some(&before));
+
+    // However many changes it carries, a delta is one input and one state.
+    let frames = a.store().len();
+    b.send(&delta, addr(&a)).unwrap();
+    receive(&mut a);
+    assert_eq!(a.store().len(), frames + 2);
+    assert_eq!(a.peer_state(&addr(&b)), Some(&after));
+
+    drop((a, b));
+    common::remove_log(&a_path);
+    common::remove_log(&b_path);
+}
+
+#[test]
+fn a_peer_state_is_copied_over_udp() {
+    let _net = net();
+    let (mut a, a_path) = mesh("mesh-sync-a");
+    let (mut b, b_path) = mesh("mesh-sync-b");
+    a.input(&[0x5A; 2000]).unwrap();
+
+    // ROOT → SYNC_REQUEST → SYNC_REPLY, until the copy verifies.
+    a.send(&Message::Root(a.identity()), addr(&b)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while b.peer_state(&addr(&a)) != Some(a.node()) {
+        assert!(Instant::now() < deadline, "sync did not finish");
+        if !(a.poll().unwrap() | b.poll().unwrap()) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    assert!(a.node().size() > 16_000);
+
+    drop((a, b));
+    common::remove_log(&a_path);
+    common::remove_log(&b_path);
+}
+
+#[test]
+fn heartbeats_ping_and_silent_peers_are_evicted() {
+    let _net = net();
+    let (mut a, a_path) = mesh("mesh-tick-a");
+    let (mut b, b_path) = mesh("mesh-tick-b");
+    a.set_peer_timeout(Duration::from_secs(30));
+    a.input(b"moved on").unwrap();
+
+    b.send(&Message::Ping(1), addr(&a)).unwrap();
+    receive(&mut a);
+    assert_eq!(drain(&mut b), 1);
+    let start = Instant::now();
+
+    // Nothing is due before the heartbeat.
+    a.tick(start).unwrap();
+    assert_eq!(drain(&mut b), 0);
+
+    // A heartbeat pings and advertises the root; the pong is timed and the
+    // root starts a sync.
+    a.tick(start + HEARTBEAT).unwrap();
+    assert_eq!(drain(&mut b), 2);
+    assert_eq!(drain(&mut a), 2);
+    assert!(a.peers().get(&addr(&b)).unwrap().rtt.is_some());
+
+    // Until b reads the replies its sync request stands unanswered, and
+    // is sent again once it is overdue.
+    b.tick(Instant::now()).unwrap();
+    assert_eq!(drain(&mut a), 0);
+    b.tick(Instant::now() + SYNC_RETRY).unwrap();
+    assert_eq!(drain(&mut a), 1);
+
+    // Silent for longer than the timeout: evicted.
+    a.tick(Instant::now() + Duration::from_secs(31)).unwrap();
+    assert!(a.peers().is_empty());
+
+    drop((a, b));
+    common::remove_log(&a_path);
+    common::remove_log(&b_path);
+}
+
+#[test]
+fn stopping_a_running_mesh_says_bye_and_checkpoints() {
+    let _net = net();
+    let (mut a, a_path) = mesh("mesh-shutdown-a");
+    let (mut b, b_path) = mesh("mesh-shutdown-b");
+
+    b.send(&Message::Announce(b.node().clone()), addr(&a))
+        .unwrap();
+    receive(&mut a);
+    drain(&mut b);
+    assert!(b.peers().contains(&addr(&a)));
+
+    let stop = a.stop_handle();
+    let (input, lines) = std::sync::mpsc::channel();
+    input.send(b"last words".to_vec()).unwrap();
+    thread::scope(|scope| {
+        let running = scope.spawn(|| a.run(lines));
+        thread::sleep(Duration::from_millis(200));
+        stop.stop();
+        running.join().unwrap().unwrap();
+    });
+
+    // The peer heard the input's delta, then BYE.
+    drain(&mut b);
+    assert!(!b.peers().contains(&addr(&a)));
+
+    let last = a.store().last_state().unwrap();
+    assert_eq!(last.as_ref(), Some(a.node()));
+    drop(a);
+    let mut reopened = Store::open(&a_path).unwrap();
+    assert_eq!(reopened.last_state().unwrap(), last);
+
+    drop(b);
+    common::remove_log(&a_path);
+    common::remove_log(&b_path);
+}
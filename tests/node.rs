@@ -0,0 +1,274 @@
+use bitvec::prelude::*;
+use hey::{codec, Bits, Node, UniversalEntropy, MAX_LEAVES};
+use proptest::prelude::*;
+use std::collections::{hash_map::DefaultHasher, BTreeSet, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+mod common;
+
+/// `Node::fold` as originally written: a full traversal per position.
+fn recursive_fold(node: &Node, entropy: &mut UniversalEntropy, pos: usize) -> bool {
+    let bit = match node {
+        Node::Bit(b) => *b,
+        Node::Compound(compound) => {
+            recursive_fold(compound.left(), entropy, pos)
+                ^ recursive_fold(compound.right(), entropy, pos)
+        }
+    };
+    bit ^ entropy.bit(pos)
+}
+
+fn bits(max: usize) -> impl Strategy<Value = Bits> {
+    prop::collection::vec(any::<bool>(), 1..max).prop_map(|v| v.into_iter().collect())
+}
+
+proptest! {
+    #[test]
+    fn bits_node_bits_roundtrip(bits in bits(2048)) {
+        let node = Node::from(bits.clone());
+        prop_assert_eq!(node.size(), bits.len());
+        prop_assert_eq!(Bits::from(&node), bits);
+    }
+
+    #[test]
+    fn bytes_node_bytes_roundtrip(bytes in prop::collection::vec(any::<u8>(), 1..512)) {
+        let node = Node::from(Bits::from_slice(&bytes));
+        prop_assert_eq!(Vec::<u8>::from(node), bytes);
+    }
+
+    #[test]
+    fn reflect_keeps_the_wider_history(a in bits(128), b in bits(128)) {
+        let mut entropy = UniversalEntropy::new();
+        let mut node = Node::from(a.clone());
+        let next = node.reflect(&mut entropy, &Node::from(b.clone()));
+        prop_assert_eq!(next.size(), a.len().max(b.len()) + 1);
+    }
+
+    #[test]
+    fn fold_matches_the_recursive_definition(node in common::node(), other in common::node()) {
+        let mut entropy = UniversalEntropy::new();
+        let op = node.op(&mut entropy, &other);
+        for pos in 0..op.len() {
+            let a = recursive_fold(&node, &mut entropy, pos);
+            let b = recursive_fold(&other, &mut entropy, pos);
+            prop_assert_eq!(node.fold(&mut entropy, pos), a);
+            prop_assert_eq!(op[pos], a ^ b ^ entropy.bit(pos));
+        }
+    }
+}
+
+#[test]
+fn reflect_folds_a_million_leaves_back_under_the_bound() {
+    let mut entropy = UniversalEntropy::new();
+    let mut node = Node::from(Bits::repeat(true, 1_000_000));
+    let next = node.reflect(&mut entropy, &Node::Bit(true));
+    assert!(next.size() <= MAX_LEAVES);
+    assert_eq!(next.size(), 1_000_001_usize.div_ceil(32));
+}
+
+#[test]
+fn growth_is_bounded_but_every_reflect_still_moves() {
+    let mut entropy = UniversalEntropy::new();
+    let mut node = Node::from(Bits::repeat(false, MAX_LEAVES - 2));
+    let mut sizes = vec![node.size()];
+    for _ in 0..4 {
+        node = node.reflect(&mut entropy, &Node::Bit(true));
+        sizes.push(node.size());
+    }
+    assert_eq!(
+        sizes,
+        [
+            MAX_LEAVES - 2,
+            MAX_LEAVES - 1,
+            MAX_LEAVES,
+            (MAX_LEAVES + 1).div_ceil(2),
+            (MAX_LEAVES + 1).div_ceil(2) + 1
+        ]
+    );
+}
+
+#[test]
+fn canonical_form_is_balanced_and_keeps_the_fold() {
+    // A left-leaning chain and a right-leaning one over the same leaves.
+    let leaves = Bits::from_slice(b"hey,");
+    let mut left = Node::Bit(leaves[0]);
+    for bit in leaves[1..].iter().by_vals() {
+        left = Node::compound(left, Node::Bit(bit));
+    }
+    let mut right = Node::Bit(leaves[leaves.len() - 1]);
+    for bit in leaves[..leaves.len() - 1].iter().by_vals().rev() {
+        right = Node::compound(Node::Bit(bit), right);
+    }
+
+    let canonical = codec::encode(&Node::from(leaves.clone()));
+    assert_eq!(codec::encode(&left.canonical()), canonical);
+    assert_eq!(codec::encode(&right.canonical()), canonical);
+
+    let mut entropy = UniversalEntropy::new();
+    let wide = Node::from(Bits::repeat(true, 3 * MAX_LEAVES));
+    assert!(wide.canonical().size() <= MAX_LEAVES);
+    assert_eq!(
+        wide.canonical().fold(&mut entropy, 7),
+        wide.fold(&mut entropy, 7)
+    );
+}
+
+#[test]
+fn deep_trees_do_not_overflow_the_stack() {
+    let depth = 1_000_000;
+    let chain = || {
+        let mut node = Node::Bit(true);
+        for _ in 0..depth {
+            node = Node::compound(Node::Bit(false), node);
+        }
+        node
+    };
+    let (node, twin) = (chain(), chain());
+
+    assert_eq!(node.size(), depth + 1);
+    assert!(node.parity());
+    assert_eq!(Bits::from(&node).count_ones(), 1);
+    assert!(node.canonical().size() <= MAX_LEAVES);
+    assert_eq!(
+        codec::decode(&codec::encode(&node)).unwrap().size(),
+        depth + 1
+    );
+    assert!(node == twin && node.cmp(&twin).is_eq());
+    assert_eq!(node.fingerprint(), twin.fingerprint());
+
+    let copy = node.clone();
+    assert_eq!(copy, node);
+    assert!(node.diff(&copy).is_empty());
+    let patch = Node::Bit(false).diff(&node);
+    assert_eq!(patch.len(), 1);
+    let mut flipped = Node::Bit(false);
+    for _ in 0..depth {
+        flipped = Node::compound(Node::Bit(false), flipped);
+    }
+    let patch = flipped.diff(&node);
+    assert_eq!(patch.len(), 1);
+    assert_eq!(patch.changes()[0].0.len(), depth);
+    flipped.apply(&patch).unwrap();
+    assert_eq!(flipped, node);
+}
+
+#[test]
+fn single_bit_is_a_leaf() {
+    assert!(matches!(Node::from(bitvec![u8, Msb0; 1]), Node::Bit(true)));
+    assert!(matches!(Node::from(bitvec![u8, Msb0; 0]), Node::Bit(false)));
+}
+
+proptest! {
+    #[test]
+    fn equality_and_order_follow_the_encoding(a in common::node(), b in common::node()) {
+        let (ea, eb) = (codec::encode(&a), codec::encode(&b));
+        prop_assert_eq!(a == b, ea == eb);
+        prop_assert_eq!(a.cmp(&b), ea.cmp(&eb));
+        prop_assert_eq!(a == b, a.fingerprint() == b.fingerprint());
+        prop_assert_eq!(&a.clone(), &a);
+    }
+
+    #[test]
+    fn equal_nodes_hash_alike(node in common::node()) {
+        let hash = |node: &Node| {
+            let mut hasher = DefaultHasher::new();
+            node.hash(&mut hasher);
+            hasher.finish()
+        };
+        let copy = codec::decode(&codec::encode(&node)).unwrap();
+        prop_assert_eq!(hash(&copy), hash(&node));
+        prop_assert_eq!(copy.fingerprint(), node.fingerprint());
+    }
+}
+
+#[test]
+fn nodes_work_as_set_and_map_keys() {
+    let a = Node::from(Bits::from_slice(b"a"));
+    let b = Node::from(Bits::from_slice(b"b"));
+    let nodes = [a.clone(), b.clone(), a.clone(), Node::Bit(true)];
+
+    assert_eq!(nodes.iter().collect::<HashSet<_>>().len(), 3);
+    let sorted: Vec<&Node> = nodes.iter().collect::<BTreeSet<_>>().into_iter().collect();
+    assert_eq!(sorted, [&Node::Bit(true), &a, &b]);
+
+    // Shape matters, not just leaves.
+    let chain = Node::compound(
+        Node::compound(Node::Bit(true), Node::Bit(false)),
+        Node::Bit(true),
+    );
+    let other = Node::compound(
+        Node::Bit(true),
+        Node::compound(Node::Bit(false), Node::Bit(true)),
+    );
+    assert_ne!(chain, other);
+    assert_ne!(chain.fingerprint(), other.fingerprint());
+    assert_eq!(chain.canonical(), other.canonical());
+}
+
+#[test]
+fn fingerprint_is_stable() {
+    assert_eq!(
+        Node::Bit(true).fingerprint().to_string(),
+        blake3::hash(&[0x00, 0x01]).to_hex().to_string()
+    );
+}
+
+/// Distinct compounds reachable from `node`, counting shared ones once.
+fn distinct_compounds(node: &Node) -> usize {
+    let mut seen = HashSet::new();
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        if let Node::Compound(compound) = node {
+            if seen.insert(Arc::as_ptr(compound)) {
+                stack.push(compound.right());
+                stack.push(compound.left());
+            }
+        }
+    }
+    seen.len()
+}
+
+#[test]
+fn subtrees_are_shared_not_copied() {
+    let same = |a: &Node, b: &Node| match (a, b) {
+        (Node::Compound(a), Node::Compound(b)) => Arc::ptr_eq(a, b),
+        _ => false,
+    };
+
+    // Identical halves are stored once, all the way down.
+    let uniform = Node::from(Bits::repeat(true, 1 << 20));
+    assert_eq!(distinct_compounds(&uniform), 20);
+    let copy = uniform.clone();
+    assert!(same(&copy, &uniform));
+
+    let mut entropy = UniversalEntropy::new();
+    let node = Node::from(entropy.bits_to_pos(1 << 16));
+    assert!(distinct_compounds(&node) < node.size() / 4);
+
+    // Equal trees built apart are the same compound, wherever they came from.
+    let decoded = codec::decode(&codec::encode(&node)).unwrap();
+    assert!(same(&decoded, &node));
+    assert!(same(&Node::from(Bits::from(&node)), &node));
+    let halves = match &node {
+        Node::Compound(root) => Node::compound(root.left().clone(), root.right().clone()),
+        Node::Bit(_) => unreachable!(),
+    };
+    assert!(same(&halves, &node));
+
+    // A patch rebuilds only the path it changes.
+    let mut patched = node.clone();
+    let Node::Compound(root) = &node else {
+        unreachable!()
+    };
+    let mut path = Bits::new();
+    path.push(false);
+    let mut patch = hey::NodePatch::new();
+    patch.replace(path, Node::Bit(true));
+    patched.apply(&patch).unwrap();
+    let Node::Compound(rebuilt) = &patched else {
+        unreachable!()
+    };
+    assert!(same(rebuilt.right(), root.right()));
+    assert_eq!(patched.size(), node.size() / 2 + 1);
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use hey::{codec, patch, Bits, Node, NodePatch};
+use proptest::prelude::*;
+
+fn bits(bits: &[bool]) -> Bits {
+    bits.iter().copied().collect()
+}
+
+proptest! {
+    #[test]
+    fn diff_then_apply_rebuilds_the_target(old in common::node(), new in common::node()) {
+        let patch = old.diff(&new);
+        let mut patched = old.clone();
+        patched.apply(&patch).unwrap();
+        prop_assert_eq!(codec::encode(&patched), codec::encode(&new));
+
+        let decoded = NodePatch::decode(&patch.encode()).unwrap();
+        prop_assert_eq!(decoded.encode(), patch.encode());
+    }
+
+    #[test]
+    fn identical_trees_need_no_changes(node in common::node()) {
+        prop_assert!(node.diff(&node.clone()).is_empty());
+    }
+
+    #[test]
+    fn truncated_patches_are_rejected(old in common::node(), new in common::node()) {
+        let bytes = old.diff(&new).encode();
+        for len in 0..bytes.len() {
+            prop_assert!(NodePatch::decode(&bytes[..len]).is_err());
+        }
+    }
+}
+
+#[test]
+fn changes_are_addressed_by_path() {
+    let old = Node::from(Bits::from_slice(&[0xF0, 0x0F]));
+    let mut flipped = Bits::from_slice(&[0xF0, 0x0F]);
+    flipped.set(13, false);
+    let new = Node::from(flipped);
+
+    // 16 leaves: a change to leaf 13 is four steps down, right-right-left-right.
+    let patch = old.diff(&new);
+    assert_eq!(patch.len(), 1);
+    assert_eq!(patch.changes()[0].0, bits(&[true, true, false, true]));
+    assert!(matches!(patch.changes()[0].1, Node::Bit(false)));
+}
+
+#[test]
+fn bad_paths_and_encodings_are_rejected() {
+    let mut patch = NodePatch::new();
+    patch.replace(bits(&[false, true]), Node::Bit(true));
+
+    let mut node = Node::from(Bits::from_slice(&[0xAA]));
+    let mut leaf = Node::Bit(false);
+    assert!(leaf.apply(&patch).is_err());
+    assert!(matches!(leaf, Node::Bit(false)));
+    // `0b1010_1010`: leaves 2 and 3 collapse into a single set leaf.
+    node.apply(&patch).unwrap();
+    assert_eq!(
+        Bits::from(&node),
+        bits(&[true, false, true, true, false, true, false])
+    );
+
+    let bytes = patch.encode();
+    let mut bad_version = bytes.clone();
+    bad_version[0] = patch::VERSION + 1;
+    assert!(NodePatch::decode(&bad_version).is_err());
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(NodePatch::decode(&trailing).is_err());
+
+    // Path bits `01` then padding: a set padding bit is corruption.
+    let mut padding = bytes.clone();
+    padding[9] |= 0x01;
+    assert!(NodePatch::decode(&padding).is_err());
+}
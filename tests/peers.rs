@@ -0,0 +1,47 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use hey::PeerTable;
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+#[test]
+fn tracks_counts_and_rtt() {
+    let t0 = Instant::now();
+    let mut peers = PeerTable::new(Duration::from_secs(10));
+
+    assert!(peers.seen(addr(4121), t0));
+    assert!(!peers.seen(addr(4121), t0));
+    peers.sent(addr(4121));
+    peers.sent(addr(9999)); // unknown peers are ignored
+
+    peers.ping(addr(4121), 7, t0);
+    assert_eq!(peers.pong(addr(4121), 8, t0), None);
+    let rtt = peers.pong(addr(4121), 7, t0 + Duration::from_millis(30));
+    assert_eq!(rtt, Some(Duration::from_millis(30)));
+
+    let peer = peers.get(&addr(4121)).unwrap();
+    assert_eq!((peer.received, peer.sent), (2, 1));
+    assert_eq!(peer.rtt, Some(Duration::from_millis(30)));
+    assert_eq!(peers.len(), 1);
+}
+
+#[test]
+fn evicts_only_silent_peers() {
+    let t0 = Instant::now();
+    let mut peers = PeerTable::new(Duration::from_secs(10));
+    peers.seen(addr(1), t0);
+    peers.seen(addr(2), t0 + Duration::from_secs(8));
+
+    assert!(peers.expire(t0 + Duration::from_secs(10)).is_empty());
+
+    let evicted = peers.expire(t0 + Duration::from_secs(11));
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].addr, addr(1));
+    assert!(peers.contains(&addr(2)));
+    assert!(!peers.contains(&addr(1)));
+}
@@ -0,0 +1,84 @@
+use hey::{stats, Bits, EntropySource, HashStream, Report, UniversalEntropy};
+
+fn bits(s: &str) -> Bits {
+    s.chars().map(|c| c == '1').collect()
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-6
+}
+
+/// The worked examples of NIST SP 800-22, section 2.
+#[test]
+fn matches_the_nist_examples() {
+    let monobit = stats::monobit(&bits("1011010101"));
+    assert!(close(monobit.p_value, 0.527_089), "{}", monobit);
+
+    let block = stats::block_frequency(&bits("0110011010"), 3);
+    assert!(close(block.statistic, 1.0), "{}", block);
+    assert!(close(block.p_value, 0.801_252), "{}", block);
+
+    let runs = stats::runs(&bits("1001101011"));
+    assert_eq!(runs.statistic, 7.0);
+    assert!(close(runs.p_value, 0.147_232), "{}", runs);
+
+    let (first, second) = stats::serial(&bits("0011011101"), 3);
+    assert!(close(first.statistic, 1.6) && close(second.statistic, 0.8));
+    assert!(close(first.p_value, 0.808_792), "{}", first);
+    assert!(close(second.p_value, 0.670_320), "{}", second);
+
+    // 100 digits of ε from the NIST example for the monobit test.
+    let epsilon = bits(concat!(
+        "11001001000011111101101010100010001000010110100011",
+        "00001000110100110001001100011001100010100010111000"
+    ));
+    assert!(close(stats::monobit(&epsilon).p_value, 0.109_599));
+    assert!(close(stats::runs(&epsilon).p_value, 0.500_798));
+    assert!(close(
+        stats::block_frequency(&epsilon, 10).p_value,
+        0.706_438
+    ));
+}
+
+#[test]
+fn biased_and_periodic_streams_fail() {
+    let ones = Report::of(&Bits::repeat(true, 10_000));
+    assert!(!ones.passed());
+    assert!(ones.failures().any(|outcome| outcome.name == "monobit"));
+
+    let periodic: Bits = (0..10_000).map(|i| i % 3 == 0 || i % 7 == 1).collect();
+    assert!(!Report::of(&periodic).passed());
+
+    let alternating: Bits = (0..10_000).map(|i| i % 2 == 0).collect();
+    let report = Report::of(&alternating);
+    assert!(stats::monobit(&alternating).passed());
+    assert!(report.failures().any(|outcome| outcome.name == "runs"));
+}
+
+#[test]
+fn the_universal_stream_passes() {
+    let report = Report::of_source(&mut UniversalEntropy::new(), 100_000);
+    assert_eq!(report.bits, 100_000);
+    assert_eq!(report.outcomes.len(), 9);
+    for outcome in &report.outcomes {
+        assert!(outcome.passed(), "{}", outcome);
+    }
+
+    let hashed = HashStream::new(b"hey,").range(0, 100_000);
+    assert!(Report::of(&hashed).passed());
+}
+
+#[test]
+fn short_input_fails_instead_of_panicking() {
+    let mut source = HashStream::new(b"short");
+    for n in 0..=40 {
+        let report = Report::of_source(&mut source, n);
+        assert_eq!(report.outcomes.len(), 9);
+        if n <= 32 {
+            assert!(!report.passed(), "{} bits passed", n);
+        }
+    }
+
+    let lagged = stats::autocorrelation(&bits("0101"), 8);
+    assert!(lagged.statistic.is_nan() && !lagged.passed());
+}
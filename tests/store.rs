@@ -0,0 +1,448 @@
+mod common;
+
+use hey::{replay, Bits, Node, Record, Retention, Source, Store, UniversalEntropy};
+
+fn input(bytes: &[u8]) -> Node {
+    Node::from(Bits::from_slice(bytes))
+}
+
+/// Journal `inputs` the way a Mesh does: input, then the resulting state.
+fn journal(store: &mut Store, inputs: &[(Source, Node)]) -> Node {
+    let mut entropy = UniversalEntropy::new();
+    let mut node = Node::root();
+    store.append_snapshot(&node).unwrap();
+    for (source, input) in inputs {
+        store.append_input(*source, input).unwrap();
+        node = node.reflect(&mut entropy, input);
+        store.append_frame(&node).unwrap();
+    }
+    node
+}
+
+#[test]
+fn records_roundtrip() {
+    let path = common::temp_path("records");
+    let mut store = Store::open(&path).unwrap();
+    let peer = "127.0.0.1:4121".parse().unwrap();
+    journal(
+        &mut store,
+        &[
+            (Source::AddrInUse, Node::Bit(true)),
+            (Source::Stdin, input(b"hey,\n")),
+            (Source::Peer(peer), input(b"hi")),
+        ],
+    );
+
+    let records: Vec<Record> = store.records().unwrap().map(Result::unwrap).collect();
+    assert_eq!(records.len(), 7);
+    assert!(matches!(records[0], Record::Snapshot(_)));
+    assert!(matches!(
+        records[1],
+        Record::Input(Source::AddrInUse, Node::Bit(true))
+    ));
+    assert!(matches!(records[5], Record::Input(Source::Peer(p), _) if p == peer));
+    assert_eq!(store.iter().unwrap().count(), 4);
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn replay_verifies_and_detects_divergence() {
+    let path = common::temp_path("replay");
+    let mut store = Store::open(&path).unwrap();
+    let last = journal(
+        &mut store,
+        &[
+            (Source::Stdin, input(b"one\n")),
+            (Source::BindError, Node::Bit(false)),
+        ],
+    );
+
+    let report = replay(&mut store).unwrap();
+    assert!(report.is_consistent());
+    assert_eq!((report.inputs, report.verified), (2, 2));
+    assert_eq!(Bits::from(report.node.as_ref().unwrap()), Bits::from(&last));
+
+    // A state that was not produced by its inputs.
+    store.append_frame(&Node::root()).unwrap();
+    let report = replay(&mut store).unwrap();
+    assert!(!report.is_consistent());
+    assert_eq!(report.divergences.len(), 1);
+    assert_eq!(report.divergences[0].record, 5);
+
+    common::remove_log(&path);
+}
+
+fn frame_ends(path: &std::path::Path) -> Vec<u64> {
+    // Walk the raw layout: 5 byte header, then [len][crc][payload] frames.
+    let bytes = std::fs::read(path).unwrap();
+    let mut ends = Vec::new();
+    let mut pos = hey::store::HEADER_LEN as usize;
+    while pos < bytes.len() {
+        let len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        pos += 8 + len;
+        ends.push(pos as u64);
+    }
+    ends
+}
+
+#[test]
+fn new_log_gets_a_header_and_foreign_files_are_refused() {
+    let path = common::temp_path("header");
+    drop(Store::open(&path).unwrap());
+    assert_eq!(std::fs::read(&path).unwrap(), b"hey,\x01");
+
+    std::fs::write(&path, b"test\n\x00\x00\x00\x01\xff").unwrap();
+    assert!(Store::open(&path).is_err());
+    std::fs::write(&path, b"he!").unwrap();
+    assert!(Store::open(&path).is_err());
+
+    // A torn header is completed.
+    std::fs::write(&path, b"hey").unwrap();
+    drop(Store::open(&path).unwrap());
+    assert_eq!(std::fs::read(&path).unwrap(), b"hey,\x01");
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn torn_tail_is_truncated_on_open() {
+    let path = common::temp_path("torn");
+    let mut store = Store::open(&path).unwrap();
+    journal(&mut store, &[(Source::Stdin, input(b"abc"))]);
+    drop(store);
+
+    let ends = frame_ends(&path);
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(ends[2] - 3).unwrap();
+    drop(file);
+
+    let mut store = Store::open(&path).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), ends[1]);
+    assert_eq!(store.records().unwrap().count(), 2);
+    assert!(store.records().unwrap().all(|r| r.is_ok()));
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn corrupt_frames_are_reported_and_oversized_lengths_refused() {
+    let path = common::temp_path("corrupt");
+    let mut store = Store::open(&path).unwrap();
+    journal(&mut store, &[(Source::Stdin, input(b"abc"))]);
+    drop(store);
+
+    let ends = frame_ends(&path);
+    let mut bytes = std::fs::read(&path).unwrap();
+
+    // Flip a payload byte in the middle frame: kept, but reported.
+    bytes[ends[0] as usize + 9] ^= 0xFF;
+    std::fs::write(&path, &bytes).unwrap();
+    let mut store = Store::open(&path).unwrap();
+    let results: Vec<bool> = store.records().unwrap().map(|r| r.is_ok()).collect();
+    assert_eq!(results, vec![true, false, true]);
+    drop(store);
+
+    // A wild length must not allocate, and is not mistaken for a torn tail.
+    let at = ends[1] as usize;
+    bytes[at..at + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+    std::fs::write(&path, &bytes).unwrap();
+    let err = Store::open(&path).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(std::fs::read(&path).unwrap(), bytes);
+
+    // With only its header left, it is a torn append after all.
+    bytes.truncate(at + 8);
+    std::fs::write(&path, &bytes).unwrap();
+    let mut store = Store::open(&path).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), ends[1]);
+    assert_eq!(store.records().unwrap().count(), 2);
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn flipped_length_mid_log_is_not_truncated() {
+    let path = common::temp_path("flipped");
+    let mut store = Store::open(&path).unwrap();
+    journal(
+        &mut store,
+        &[
+            (Source::Stdin, input(b"abc")),
+            (Source::Stdin, input(b"de")),
+        ],
+    );
+    drop(store);
+
+    let ends = frame_ends(&path);
+    assert_eq!(ends.len(), 5);
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[ends[0] as usize] ^= 0x80;
+    std::fs::write(&path, &bytes).unwrap();
+    std::fs::remove_file(hey::store::index_path(&path)).unwrap();
+
+    let err = Store::open(&path).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(std::fs::read(&path).unwrap(), bytes);
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn index_gives_random_and_reverse_access() {
+    let path = common::temp_path("index");
+    let mut store = Store::open(&path).unwrap();
+    let inputs: Vec<_> = (0u8..5).map(|i| (Source::Stdin, input(&[i]))).collect();
+    let last = journal(&mut store, &inputs);
+
+    assert_eq!(store.len(), 11);
+    assert!(matches!(store.get(0).unwrap(), Some(Record::Snapshot(_))));
+    assert!(matches!(
+        store.get(3).unwrap(),
+        Some(Record::Input(Source::Stdin, _))
+    ));
+    assert!(store.get(11).unwrap().is_none());
+    assert!(matches!(
+        store.last().unwrap(),
+        Some(Record::State(_) | Record::Delta(_))
+    ));
+    assert_eq!(
+        Bits::from(&store.last_state().unwrap().unwrap()),
+        Bits::from(&last)
+    );
+
+    let forward: Vec<Bits> = store
+        .iter()
+        .unwrap()
+        .map(|n| Bits::from(&n.unwrap()))
+        .collect();
+    let mut backward: Vec<Bits> = (0..store.len())
+        .rev()
+        .filter_map(|n| store.state_at(n).unwrap())
+        .map(|n| Bits::from(&n))
+        .collect();
+    backward.reverse();
+    assert_eq!(forward, backward);
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn missing_or_stale_index_is_rebuilt() {
+    let path = common::temp_path("reindex");
+    let mut store = Store::open(&path).unwrap();
+    journal(&mut store, &[(Source::Stdin, input(b"abc"))]);
+    drop(store);
+    let index = hey::store::index_path(&path);
+    let good = std::fs::read(&index).unwrap();
+    assert_eq!(good.len(), 3 * 8);
+
+    std::fs::remove_file(&index).unwrap();
+    assert_eq!(Store::open(&path).unwrap().len(), 3);
+    assert_eq!(std::fs::read(&index).unwrap(), good);
+
+    // Points into the middle of a frame.
+    let mut stale = good.clone();
+    stale[23] += 1;
+    std::fs::write(&index, &stale).unwrap();
+    assert_eq!(Store::open(&path).unwrap().len(), 3);
+    assert_eq!(std::fs::read(&index).unwrap(), good);
+
+    // Lagging behind the log (crash between the two appends).
+    std::fs::write(&index, &good[..8]).unwrap();
+    let mut store = Store::open(&path).unwrap();
+    assert_eq!(store.len(), 3);
+    assert!(store.get(2).unwrap().is_some());
+    assert_eq!(std::fs::read(&index).unwrap(), good);
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn foreign_index_is_rebuilt() {
+    let path = common::temp_path("foreign");
+    let other = common::temp_path("foreign-other");
+    let mut store = Store::open(&path).unwrap();
+    journal(&mut store, &[(Source::Stdin, input(b"abc"))]);
+    drop(store);
+    let mut store = Store::open(&other).unwrap();
+    let inputs: Vec<_> = (0u8..20).map(|i| (Source::Stdin, input(&[i; 9]))).collect();
+    journal(&mut store, &inputs);
+    drop(store);
+
+    let index = hey::store::index_path(&path);
+    let good = std::fs::read(&index).unwrap();
+    std::fs::copy(hey::store::index_path(&other), &index).unwrap();
+    assert_eq!(Store::open(&path).unwrap().len(), 3);
+    assert_eq!(std::fs::read(&index).unwrap(), good);
+
+    // An entry landing where the bytes read as an impossible length.
+    let bytes = std::fs::read(&path).unwrap();
+    let bogus = (hey::store::HEADER_LEN as usize + 1..bytes.len() - 8)
+        .find(|&pos| {
+            let len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap());
+            len > hey::store::MAX_FRAME
+        })
+        .expect("some offset reads as an oversized length") as u64;
+    let mut stale = hey::store::HEADER_LEN.to_be_bytes().to_vec();
+    stale.extend_from_slice(&bogus.to_be_bytes());
+    std::fs::write(&index, &stale).unwrap();
+    assert_eq!(Store::open(&path).unwrap().len(), 3);
+    assert_eq!(std::fs::read(&index).unwrap(), good);
+
+    common::remove_log(&path);
+    common::remove_log(&other);
+}
+
+#[test]
+fn compaction_keeps_a_replayable_tail() {
+    let path = common::temp_path("compact");
+    let mut store = Store::open(&path).unwrap();
+    let inputs: Vec<_> = (0u8..10).map(|i| (Source::Stdin, input(&[i, i]))).collect();
+    let last = journal(&mut store, &inputs);
+    assert_eq!(store.len(), 21);
+    let old_index = std::fs::read(hey::store::index_path(&path)).unwrap();
+
+    let compaction = store
+        .compact(Retention {
+            frames: Some(5),
+            bytes: None,
+        })
+        .unwrap();
+    assert_eq!(compaction.frames_before, 21);
+    assert!(compaction.bytes_after < compaction.bytes_before);
+
+    // Snapshot of the first kept state, then the tail after it.
+    assert!(matches!(store.get(0).unwrap(), Some(Record::Snapshot(_))));
+    assert!(store.len() <= 5);
+    assert_eq!(
+        Bits::from(&store.last_state().unwrap().unwrap()),
+        Bits::from(&last)
+    );
+    let report = replay(&mut store).unwrap();
+    assert!(report.is_consistent());
+    assert!(report.verified > 0);
+
+    // Still appendable, and the rebuilt index survives a reopen.
+    store.append_frame(&last).unwrap();
+    let len = store.len();
+    drop(store);
+    assert_eq!(Store::open(&path).unwrap().len(), len);
+
+    // An index left over from before the compaction is rebuilt too.
+    std::fs::write(hey::store::index_path(&path), old_index).unwrap();
+    assert_eq!(Store::open(&path).unwrap().len(), len);
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn compaction_to_nothing_leaves_the_current_state() {
+    let path = common::temp_path("compact-all");
+    let mut store = Store::open(&path).unwrap();
+    let last = journal(&mut store, &[(Source::Stdin, input(b"x"))]);
+
+    store
+        .compact(Retention {
+            frames: None,
+            bytes: Some(0),
+        })
+        .unwrap();
+    assert_eq!(store.len(), 1);
+    match store.get(0).unwrap() {
+        Some(Record::Snapshot(node)) => assert_eq!(Bits::from(&node), Bits::from(&last)),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // Nothing left to drop.
+    let again = store
+        .compact(Retention {
+            frames: Some(0),
+            bytes: None,
+        })
+        .unwrap();
+    assert_eq!(again.frames_before, again.frames_after);
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn states_are_written_as_deltas_and_rebuilt() {
+    let path = common::temp_path("delta");
+    let mut store = Store::open(&path).unwrap();
+
+    // A long-lived node whose state changes one leaf at a time.
+    let mut bits = Bits::from_slice(&[0x5a; 64]);
+    let mut states = vec![Node::from(bits.clone())];
+    store.append_snapshot(&states[0]).unwrap();
+    for i in 0..100 {
+        let at = i * 5 % bits.len();
+        let flipped = !bits[at];
+        bits.set(at, flipped);
+        states.push(Node::from(bits.clone()));
+        store.append_frame(states.last().unwrap()).unwrap();
+    }
+
+    let records: Vec<Record> = store.records().unwrap().map(Result::unwrap).collect();
+    let deltas = records
+        .iter()
+        .filter(|r| matches!(r, Record::Delta(changes) if changes.len() == 1))
+        .count();
+    let full = records
+        .iter()
+        .filter(|r| matches!(r, Record::State(_)))
+        .count();
+    assert_eq!((deltas, full), (99, 1));
+    assert!(matches!(
+        records[hey::store::KEYFRAME_INTERVAL + 1],
+        Record::State(_)
+    ));
+
+    // Far smaller than writing every state in full.
+    let one = hey::encode(&states[0]).len() as u64;
+    assert!(std::fs::metadata(&path).unwrap().len() < states.len() as u64 * one / 4);
+
+    let expected: Vec<Bits> = states.iter().map(Bits::from).collect();
+    let forward: Vec<Bits> = store
+        .iter()
+        .unwrap()
+        .map(|n| Bits::from(&n.unwrap()))
+        .collect();
+    assert_eq!(forward, expected);
+    for (n, state) in expected.iter().enumerate() {
+        assert_eq!(&Bits::from(&store.state_at(n).unwrap().unwrap()), state);
+    }
+
+    // Deltas survive a reopen; the first append after it is a full state.
+    drop(store);
+    let mut store = Store::open(&path).unwrap();
+    assert_eq!(
+        Bits::from(&store.last_state().unwrap().unwrap()),
+        expected[100]
+    );
+    store.append_frame(&states[0]).unwrap();
+    assert!(matches!(store.last().unwrap(), Some(Record::State(_))));
+
+    common::remove_log(&path);
+}
+
+#[test]
+fn a_journal_with_deltas_replays() {
+    let path = common::temp_path("delta-replay");
+    let mut store = Store::open(&path).unwrap();
+    let inputs: Vec<_> = (0u8..20)
+        .map(|i| (Source::Stdin, Node::Bit(i % 3 == 0)))
+        .collect();
+    let last = journal(&mut store, &inputs);
+
+    // A shutdown checkpoint of an unchanged state is an empty delta.
+    store.append_frame(&last).unwrap();
+    assert!(matches!(store.last().unwrap(), Some(Record::Delta(changes)) if changes.is_empty()));
+
+    let report = replay(&mut store).unwrap();
+    assert!(report.is_consistent());
+    assert_eq!(report.verified, 21);
+    assert_eq!(Bits::from(report.node.as_ref().unwrap()), Bits::from(&last));
+
+    common::remove_log(&path);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use hey::{sync, Bits, Digest, MerkleTree, Message, Node, SyncEntry, SyncSession, SIZE};
+use proptest::prelude::*;
+
+/// Run `session` against a peer holding `node`, every message going through
+/// the wire encoding. Returns how many requests were sent.
+fn exchange(session: &mut SyncSession, node: &Node) -> usize {
+    let tree = MerkleTree::new(node);
+    let mut requests = 0;
+    loop {
+        let paths = session.request();
+        if paths.is_empty() {
+            return requests;
+        }
+        requests += 1;
+
+        let request = Message::SyncRequest {
+            root: session.root(),
+            paths,
+        };
+        let Message::SyncRequest { root, paths } = Message::decode(&request.encode()).unwrap()
+        else {
+            panic!("not a sync request");
+        };
+        assert_eq!(root, tree.root());
+
+        for reply in Message::sync_replies(root, sync::answer(node, &tree, &paths).unwrap()) {
+            let packet = reply.encode();
+            assert!(packet.len() <= SIZE);
+            let Message::SyncReply { entries, .. } = Message::decode(&packet).unwrap() else {
+                panic!("not a sync reply");
+            };
+            for (path, entry) in entries {
+                session.receive(path, entry).unwrap();
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn copies_a_peer_state_from_scratch(node in common::node()) {
+        let mut session = SyncSession::new(node.fingerprint(), Vec::new());
+        exchange(&mut session, &node);
+        prop_assert!(session.is_complete());
+        prop_assert_eq!(session.finish(), Some(node));
+    }
+
+    #[test]
+    fn copies_using_a_local_source(a in common::node(), b in common::node()) {
+        let mut session = SyncSession::new(b.fingerprint(), vec![a]);
+        exchange(&mut session, &b);
+        prop_assert_eq!(session.finish(), Some(b));
+    }
+}
+
+#[test]
+fn large_state_spans_many_requests() {
+    let node = Node::from(Bits::from_slice(&[0x5A; 8192]));
+    let mut session = SyncSession::new(node.fingerprint(), Vec::new());
+    assert!(exchange(&mut session, &node) > 1);
+    assert_eq!(session.finish(), Some(node));
+}
+
+#[test]
+fn only_the_difference_is_fetched() {
+    let mut bits = Bits::from_slice(&[0xA5; 1024]);
+    let old = Node::from(bits.clone());
+    let flipped = !bits[5000];
+    bits.set(5000, flipped);
+    let new = Node::from(bits);
+
+    let mut session = SyncSession::new(new.fingerprint(), vec![old]);
+    exchange(&mut session, &new);
+
+    // One compound per level down to an inlined subtree, and that subtree.
+    let depth = (new.size() as f64).log2().ceil() as usize;
+    assert!(session.fetched() <= depth);
+    assert_eq!(session.finish(), Some(new));
+}
+
+#[test]
+fn tampered_answers_are_rejected() {
+    let node = Node::from(Bits::from_slice(&(0..64).collect::<Vec<u8>>()));
+    let tree = MerkleTree::new(&node);
+    let (left, right) = tree.children(&Bits::new()).unwrap();
+
+    let mut session = SyncSession::new(node.fingerprint(), Vec::new());
+    assert_eq!(session.request(), vec![Bits::new()]);
+    assert!(session
+        .receive(Bits::new(), SyncEntry::Children(right, left))
+        .is_err());
+    assert!(session
+        .receive(Bits::new(), SyncEntry::Subtree(Node::Bit(true)))
+        .is_err());
+    assert!(!session.is_complete());
+
+    // An answer for a path nobody asked about is ignored.
+    let mut path = Bits::new();
+    path.push(true);
+    session
+        .receive(path, SyncEntry::Subtree(Node::Bit(false)))
+        .unwrap();
+
+    session
+        .receive(Bits::new(), SyncEntry::Children(left, right))
+        .unwrap();
+    exchange(&mut session, &node);
+    assert_eq!(session.finish(), Some(node));
+}
+
+#[test]
+fn unfinished_sessions_yield_nothing() {
+    let session = SyncSession::new(Digest::leaf(true), Vec::new());
+    assert!(!session.is_complete());
+    assert_eq!(session.finish(), None);
+
+    // Nothing to fetch when the state is already held.
+    let session = SyncSession::new(Digest::leaf(true), vec![Node::Bit(true)]);
+    assert_eq!(session.fetched(), 0);
+    assert_eq!(session.finish(), Some(Node::Bit(true)));
+}
+
+#[test]
+fn deep_sources_are_indexed_without_copying_paths() {
+    // A right-leaning chain as deep as a full datagram of ANNOUNCE allows.
+    let mut chain = Node::Bit(false);
+    let mut inner = None;
+    for i in 0..170_000 {
+        chain = Node::compound(Node::Bit(i % 3 == 0), chain);
+        if i == 100_000 {
+            inner = Some(chain.clone());
+        }
+    }
+    let inner = inner.unwrap();
+
+    let session = SyncSession::new(inner.fingerprint(), vec![chain]);
+    assert!(session.is_complete());
+    assert_eq!(session.finish(), Some(inner));
+}
+
+#[test]
+fn oversized_and_repeated_requests_are_not_amplified() {
+    let node = Node::from(Bits::from_slice(&[0x5A; 8192]));
+    let tree = MerkleTree::new(&node);
+
+    let flood = vec![Bits::new(); sync::REQUEST_BUDGET];
+    assert!(sync::answer(&node, &tree, &flood).is_err());
+
+    let repeated = vec![Bits::new(); sync::REQUEST_BUDGET / 2];
+    assert_eq!(sync::answer(&node, &tree, &repeated).unwrap().len(), 1);
+}
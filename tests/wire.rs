@@ -0,0 +1,163 @@
+mod common;
+
+use hey::{wire, Bits, MerkleTree, Message, Node, SyncEntry};
+use proptest::prelude::*;
+
+fn kind_of(packet: &[u8]) -> u8 {
+    Message::decode(packet).unwrap().kind()
+}
+
+proptest! {
+    #[test]
+    fn node_messages_roundtrip(node in common::node(), other in common::node()) {
+        match Message::decode(&Message::Announce(node.clone()).encode()).unwrap() {
+            Message::Announce(n) => prop_assert_eq!(Bits::from(&n), Bits::from(&node)),
+            other => prop_assert!(false, "unexpected {:?}", other),
+        }
+
+        let delta = Message::StateDelta(node.diff(&other));
+        match Message::decode(&delta.encode()).unwrap() {
+            Message::StateDelta(patch) => {
+                let mut patched = node.clone();
+                patched.apply(&patch).unwrap();
+                prop_assert_eq!(Bits::from(&patched), Bits::from(&other));
+            }
+            other => prop_assert!(false, "unexpected {:?}", other),
+        }
+
+        let tree = MerkleTree::new(&node);
+        let mut path = Bits::new();
+        path.push(true);
+        let entries = vec![
+            (Bits::new(), SyncEntry::Subtree(other.clone())),
+            (path.clone(), SyncEntry::Children(tree.root(), other.fingerprint())),
+        ];
+        let reply = Message::SyncReply { root: tree.root(), entries: entries.clone() };
+        match Message::decode(&reply.encode()).unwrap() {
+            Message::SyncReply { root, entries: decoded } => {
+                prop_assert_eq!(root, tree.root());
+                prop_assert_eq!(decoded, entries);
+            }
+            other => prop_assert!(false, "unexpected {:?}", other),
+        }
+
+        let request = Message::SyncRequest { root: tree.root(), paths: vec![Bits::new(), path.clone()] };
+        match Message::decode(&request.encode()).unwrap() {
+            Message::SyncRequest { root, paths } => {
+                prop_assert_eq!(root, tree.root());
+                prop_assert_eq!(paths, vec![Bits::new(), path]);
+            }
+            other => prop_assert!(false, "unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(packet in prop::collection::vec(any::<u8>(), 0..64)) {
+        let _ = Message::decode(&packet);
+    }
+}
+
+#[test]
+fn simple_messages_roundtrip() {
+    assert_eq!(kind_of(&Message::Ack.encode()), wire::ACK);
+    assert_eq!(kind_of(&Message::Bye.encode()), wire::BYE);
+    assert!(matches!(
+        Message::decode(&Message::Ping(7).encode()).unwrap(),
+        Message::Ping(7)
+    ));
+    assert!(matches!(
+        Message::decode(&Message::Pong(u64::MAX).encode()).unwrap(),
+        Message::Pong(u64::MAX)
+    ));
+    match Message::decode(&Message::Chat(b"hey,\n".to_vec()).encode()).unwrap() {
+        Message::Chat(data) => assert_eq!(data, b"hey,\n"),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn rejects_malformed_packets() {
+    // The old bare handshake.
+    assert!(Message::decode(hey::ROOT).is_err());
+
+    let ack = Message::Ack.encode();
+    let mut bad_version = ack.clone();
+    bad_version[0] = wire::VERSION + 1;
+    assert!(Message::decode(&bad_version).is_err());
+
+    let mut bad_type = ack.clone();
+    bad_type[1] = 0xEE;
+    assert!(Message::decode(&bad_type).is_err());
+
+    let mut trailing = ack.clone();
+    trailing.push(0);
+    assert!(Message::decode(&trailing).is_err());
+
+    let chat = Message::Chat(b"hi".to_vec()).encode();
+    assert!(Message::decode(&chat[..chat.len() - 1]).is_err());
+    assert!(Message::decode(&Message::Chat(Vec::new()).encode()).is_err());
+
+    let root = Message::Root(Node::Bit(true).fingerprint()).encode();
+    assert!(matches!(Message::decode(&root).unwrap(), Message::Root(_)));
+    assert!(Message::decode(&root[..root.len() - 1]).is_err());
+
+    let request = Message::SyncRequest {
+        root: Node::Bit(true).fingerprint(),
+        paths: vec![Bits::repeat(true, 3)],
+    };
+    let mut padded = request.encode();
+    *padded.last_mut().unwrap() |= 0x01;
+    assert!(Message::decode(&padded).is_err());
+
+    let announce = Message::Announce(Node::Bit(true)).encode();
+    let mut corrupt = announce.clone();
+    corrupt[wire::HEADER_LEN] ^= 0xFF;
+    assert!(Message::decode(&corrupt).is_err());
+}
+
+#[test]
+fn largest_states_fit_a_datagram() {
+    let mut entropy = hey::UniversalEntropy::new();
+    let leaves = entropy.bits_to_pos(hey::MAX_LEAVES);
+    let node = Node::from(leaves);
+    assert_eq!(node.size(), hey::MAX_LEAVES);
+
+    let announce = Message::Announce(node.clone()).encode();
+    assert!(announce.len() > hey::SIZE);
+    assert!(announce.len() <= wire::MAX_DATAGRAM);
+    assert_eq!(kind_of(&announce), wire::ANNOUNCE);
+
+    let delta = Message::StateDelta(Node::root().diff(&node)).encode();
+    assert!(delta.len() <= wire::MAX_DATAGRAM);
+    assert_eq!(kind_of(&delta), wire::STATE_DELTA);
+}
+
+#[test]
+fn large_inputs_still_reach_peers() {
+    // One long stdin line folded into the starting state.
+    let mut entropy = hey::UniversalEntropy::new();
+    let mut before = Node::root();
+    let line = Node::from(Bits::from_vec(vec![b'x'; 1400]));
+    let after = before.reflect(&mut entropy, &line);
+
+    let delta = Message::state_delta(before.diff(&after), after.fingerprint()).encode();
+    assert!(delta.len() > hey::SIZE);
+    match Message::decode(&delta).unwrap() {
+        Message::StateDelta(patch) => {
+            let mut patched = before.clone();
+            patched.apply(&patch).unwrap();
+            assert_eq!(patched, after);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // A delta no datagram can hold falls back to the root, to sync from.
+    let mut huge = hey::NodePatch::new();
+    for _ in 0..20 {
+        huge.replace(Bits::new(), after.clone());
+    }
+    match Message::state_delta(huge, after.fingerprint()) {
+        Message::Root(root) => assert_eq!(root, after.fingerprint()),
+        other => panic!("unexpected {:?}", other),
+    }
+}